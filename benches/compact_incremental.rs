@@ -0,0 +1,49 @@
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use h3o::{CellIndex, LatLng, Resolution};
+use rasterh3::CellCoverage;
+
+const N_BATCHES: usize = 1_000;
+
+/// The cells of a large disk, inserted in batches of neighbouring cells.
+fn cell_batches() -> Vec<Vec<CellIndex>> {
+    let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Ten);
+    let cells = center.grid_disk::<Vec<_>>(150);
+    let batch_size = cells.len().div_ceil(N_BATCHES);
+    cells.chunks(batch_size).map(<[_]>::to_vec).collect()
+}
+
+fn insert_batches(batches: &[Vec<CellIndex>], compact: impl Fn(&mut CellCoverage)) -> CellCoverage {
+    let mut coverage = CellCoverage::default();
+    for batch in batches {
+        for cell in batch {
+            coverage.insert(*cell);
+        }
+        compact(&mut coverage);
+    }
+    coverage
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let batches = cell_batches();
+
+    let mut group = c.benchmark_group("compaction after each inserted batch");
+    group.sample_size(10);
+    group.bench_function("compact", |b| {
+        b.iter_batched(
+            || batches.clone(),
+            |batches| insert_batches(&batches, |coverage| coverage.compact().unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("compact_incremental", |b| {
+        b.iter_batched(
+            || batches.clone(),
+            |batches| insert_batches(&batches, |coverage| coverage.compact_incremental().unwrap()),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+//! Run with `cargo bench --features bench-internals --bench convert_sparse`.
+use criterion::{Criterion, criterion_group, criterion_main};
+use geo::AffineTransform;
+use h3o::Resolution;
+use ndarray::Array2;
+use rasterh3::{AxisOrder, H3Converter, SparseDetection};
+
+const SIZE: usize = 2000;
+
+/// A band of data along the diagonal of the array.
+fn diagonal_fixture() -> Array2<u8> {
+    Array2::from_shape_fn((SIZE, SIZE), |(r, c)| u8::from(r.abs_diff(c) < 10))
+}
+
+/// Small squares of data scattered over the array.
+fn scattered_fixture() -> Array2<u8> {
+    let mut arr = Array2::zeros((SIZE, SIZE));
+    // simple deterministic linear congruential generator
+    let mut state = 42_u64;
+    let mut next = |max: usize| {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        ((state >> 33) as usize) % max
+    };
+    for _ in 0..200 {
+        let (r, c) = (next(SIZE - 15), next(SIZE - 15));
+        arr.slice_mut(ndarray::s![r..r + 15, c..c + 15]).fill(1);
+    }
+    arr
+}
+
+fn criterion_benchmark(c: &mut Criterion) {
+    // same georeference as the included r.tiff
+    let transform =
+        rasterh3::transform::from_gdal(&[8.11377, 0.001196505, 0.0, 49.40792, 0.0, -0.001215135]);
+    let h3_res = Resolution::Ten;
+
+    let mut group = c.benchmark_group("sparse raster conversion");
+    group.sample_size(10);
+    for (name, fixture) in [
+        ("diagonal", diagonal_fixture()),
+        ("scattered", scattered_fixture()),
+    ] {
+        let view = fixture.view();
+        for (detection_name, sparse_detection) in [
+            ("block_occupancy", SparseDetection::BlockOccupancy),
+            ("empty_lines", SparseDetection::EmptyLines),
+        ] {
+            group.bench_function(
+                format!("convert_sparse_{name}_{detection_name}_h3_res_{h3_res}"),
+                |b| {
+                    b.iter(|| {
+                        convert(
+                            &view,
+                            &transform,
+                            sparse_detection,
+                            std::hint::black_box(h3_res),
+                        )
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn convert(
+    view: &ndarray::ArrayView2<u8>,
+    transform: &AffineTransform<f64>,
+    sparse_detection: SparseDetection,
+    h3_res: Resolution,
+) {
+    let conv = H3Converter::new(view, &Some(0_u8), transform, AxisOrder::YX)
+        .with_sparse_detection(sparse_detection);
+    let _ = conv.to_h3(h3_res, false).unwrap();
+}
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,469 @@
+//! Aggregation of cells to coarser resolutions.
+//!
+//! The functions in this module operate on the value -> [`CellCoverage`] maps returned by
+//! [`H3Converter::to_h3`](crate::H3Converter::to_h3) and derive the values of parent cells
+//! from the values of their children.
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hash};
+
+use ahash::HashMap;
+use h3o::{CellIndex, Resolution};
+
+use crate::{CellCoverage, Error};
+
+/// Rule to derive the values of a parent cell from the values of its children.
+pub trait Aggregation<K> {
+    /// Aggregate the values of the children of a parent cell.
+    ///
+    /// `counts` contains each distinct value found among the children together with the number
+    /// of children carrying it. `n_children` is the total number of children of the parent,
+    /// including the children without any value.
+    ///
+    /// The parent cell will be added to the coverages of all returned values.
+    fn aggregate(&self, counts: &[(&K, u64)], n_children: u64) -> Vec<K>;
+}
+
+/// The value held by more than half of all children.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Majority;
+
+impl<K: Clone> Aggregation<K> for Majority {
+    fn aggregate(&self, counts: &[(&K, u64)], n_children: u64) -> Vec<K> {
+        counts
+            .iter()
+            .find(|(_, count)| *count * 2 > n_children)
+            .map(|(value, _)| vec![(*value).clone()])
+            .unwrap_or_default()
+    }
+}
+
+/// The most frequent value among the children.
+///
+/// Parents where multiple values are tied for being the most frequent one get no value.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Mode;
+
+impl<K: Clone> Aggregation<K> for Mode {
+    fn aggregate(&self, counts: &[(&K, u64)], _n_children: u64) -> Vec<K> {
+        let Some(max_count) = counts.iter().map(|(_, count)| *count).max() else {
+            return vec![];
+        };
+        let mut most_frequent = counts.iter().filter(|(_, count)| *count == max_count);
+        match (most_frequent.next(), most_frequent.next()) {
+            (Some((value, _)), None) => vec![(*value).clone()],
+            _ => vec![],
+        }
+    }
+}
+
+/// The value shared by all children. Parents with children of differing values or
+/// children without a value get no value.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct All;
+
+impl<K: Clone> Aggregation<K> for All {
+    fn aggregate(&self, counts: &[(&K, u64)], n_children: u64) -> Vec<K> {
+        counts
+            .iter()
+            .find(|(_, count)| *count == n_children)
+            .map(|(value, _)| vec![(*value).clone()])
+            .unwrap_or_default()
+    }
+}
+
+/// Every value found among the children. The parent cell gets added to the
+/// coverages of all of these values, so the coverages will overlap.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Any;
+
+impl<K: Clone> Aggregation<K> for Any {
+    fn aggregate(&self, counts: &[(&K, u64)], _n_children: u64) -> Vec<K> {
+        counts.iter().map(|(value, _)| (*value).clone()).collect()
+    }
+}
+
+/// The mean of the values of all children having a value.
+///
+/// The contained function converts the mean back to the value type, for example
+/// `Mean(|mean: f64| mean.round() as u8)`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Mean<F>(pub F);
+
+impl<K, F> Aggregation<K> for Mean<F>
+where
+    K: Copy + Into<f64>,
+    F: Fn(f64) -> K,
+{
+    fn aggregate(&self, counts: &[(&K, u64)], _n_children: u64) -> Vec<K> {
+        let (sum, n) = counts
+            .iter()
+            .fold((0.0, 0_u64), |(sum, n), (value, count)| {
+                (sum + (**value).into() * *count as f64, n + *count)
+            });
+        if n == 0 {
+            vec![]
+        } else {
+            vec![(self.0)(sum / n as f64)]
+        }
+    }
+}
+
+/// How to choose between multiple values being equally frequent.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The parent gets no value.
+    #[default]
+    Skip,
+
+    /// Choose the lowest of the tied values.
+    Lowest,
+
+    /// Choose the highest of the tied values.
+    Highest,
+}
+
+/// Categorical resampling assigning each parent the most frequent value among its children.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Categorical {
+    /// How to choose between multiple values being equally frequent.
+    pub tie_break: TieBreak,
+
+    /// The minimum fraction (`0.0..=1.0`) of the children of a parent which must have a value.
+    /// Parents below this threshold, for example near nodata regions, get no value.
+    pub min_coverage: f64,
+}
+
+impl<K: Clone + Ord> Aggregation<K> for Categorical {
+    fn aggregate(&self, counts: &[(&K, u64)], n_children: u64) -> Vec<K> {
+        let n_with_value = counts.iter().map(|(_, count)| *count).sum::<u64>();
+        if n_children == 0 || (n_with_value as f64 / n_children as f64) < self.min_coverage {
+            return vec![];
+        }
+        let Some(max_count) = counts.iter().map(|(_, count)| *count).max() else {
+            return vec![];
+        };
+        let mut most_frequent = counts
+            .iter()
+            .filter(|(_, count)| *count == max_count)
+            .map(|(value, _)| *value);
+
+        let value = match self.tie_break {
+            TieBreak::Skip => match (most_frequent.next(), most_frequent.next()) {
+                (Some(value), None) => Some(value),
+                _ => None,
+            },
+            TieBreak::Lowest => most_frequent.min(),
+            TieBreak::Highest => most_frequent.max(),
+        };
+        value.map(|value| vec![value.clone()]).unwrap_or_default()
+    }
+}
+
+/// Coarsen the cells of `map` to `resolution`.
+///
+/// Cells at `resolution` or coarser are kept as they are. The values of the parents of the
+/// finer cells are determined using `aggregation`, counting the children at the finest
+/// resolution found in `map`.
+pub(crate) fn coarsen<K, A, S>(
+    map: &std::collections::HashMap<K, CellCoverage, S>,
+    resolution: Resolution,
+    aggregation: &A,
+) -> HashMap<K, CellCoverage>
+where
+    K: Clone + Eq + Hash,
+    A: Aggregation<K> + ?Sized,
+    S: BuildHasher,
+{
+    let mut coarsened = HashMap::<K, CellCoverage>::default();
+    let Some(unit_resolution) = map
+        .values()
+        .flat_map(|coverage| coverage.compacted_iter())
+        .map(|cell| cell.resolution())
+        .max()
+    else {
+        return coarsened;
+    };
+
+    let mut counts_by_parent = HashMap::<CellIndex, Vec<(&K, u64)>>::default();
+    for (value, coverage) in map.iter() {
+        for cell in coverage.compacted_iter() {
+            let Some(parent) = cell
+                .parent(resolution)
+                .filter(|_| cell.resolution() > resolution)
+            else {
+                coarsened.entry(value.clone()).or_default().insert(cell);
+                continue;
+            };
+            let count = cell.children_count(unit_resolution);
+            let counts = counts_by_parent.entry(parent).or_default();
+            match counts.iter_mut().find(|(v, _)| *v == value) {
+                Some((_, value_count)) => *value_count += count,
+                None => counts.push((value, count)),
+            }
+        }
+    }
+
+    for (parent, counts) in counts_by_parent {
+        for value in aggregation.aggregate(&counts, parent.children_count(unit_resolution)) {
+            coarsened.entry(value).or_default().insert(parent);
+        }
+    }
+    coarsened
+}
+
+/// Coarsen the cells of `map` to `resolution` and finalize the resulting coverages.
+///
+/// With `compact` the coverages are compacted, otherwise they only contain cells
+/// at `resolution`.
+pub(crate) fn coarsen_finalized<K, A, S>(
+    map: &std::collections::HashMap<K, CellCoverage, S>,
+    resolution: Resolution,
+    aggregation: &A,
+    compact: bool,
+) -> Result<HashMap<K, CellCoverage>, Error>
+where
+    K: Clone + Eq + Hash,
+    A: Aggregation<K> + ?Sized,
+    S: BuildHasher,
+{
+    let mut coarsened = coarsen(map, resolution, aggregation);
+    for coverage in coarsened.values_mut() {
+        if compact {
+            coverage.compact()?;
+        } else {
+            coverage.normalize(resolution);
+        }
+    }
+    Ok(coarsened)
+}
+
+/// Build a pyramid of maps at multiple resolutions from the value -> [`CellCoverage`] map of
+/// a single conversion at the finest resolution.
+///
+/// Each level is derived from `map` itself, so it matches coarsening `map` directly to the
+/// resolution of the level using [`CellCoverageMap::coarsen`](crate::CellCoverageMap::coarsen).
+/// Deriving a level from the next finer level instead could change the result, as the
+/// aggregation of the intermediate level discards the counts of the children.
+///
+/// The cost therefore grows with the number of levels times the size of `map`, as every
+/// level iterates over all cells of `map` again.
+///
+/// With `compact` the coverages of each level are compacted, otherwise they only
+/// contain cells at the resolution of the level.
+pub fn pyramid<K, A, S>(
+    map: &std::collections::HashMap<K, CellCoverage, S>,
+    resolutions: impl IntoIterator<Item = Resolution>,
+    aggregation: &A,
+    compact: bool,
+) -> Result<BTreeMap<Resolution, HashMap<K, CellCoverage>>, Error>
+where
+    K: Clone + Eq + Hash,
+    A: Aggregation<K> + ?Sized,
+    S: BuildHasher,
+{
+    let mut resolutions = resolutions.into_iter().collect::<Vec<_>>();
+    resolutions.sort_unstable();
+    resolutions.dedup();
+
+    resolutions
+        .into_iter()
+        .map(|resolution| {
+            coarsen_finalized(map, resolution, aggregation, compact)
+                .map(|level| (resolution, level))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use h3o::{CellIndex, LatLng, Resolution};
+
+    use crate::aggregation::{
+        All, Any, Categorical, Majority, Mean, Mode, TieBreak, coarsen, pyramid,
+    };
+    use crate::{AxisOrder, CellCoverage, CellCoverageMap, H3Converter};
+
+    fn parent_cell() -> CellIndex {
+        LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Eight)
+    }
+
+    /// Five children of the parent are 1, one child is 2 and one child has no value.
+    fn map_with_mixed_children() -> HashMap<u8, CellCoverage> {
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        for (i, child) in parent_cell().children(Resolution::Nine).enumerate() {
+            match i {
+                0..5 => map.entry(1).or_default().insert(child),
+                5 => map.entry(2).or_default().insert(child),
+                _ => (),
+            }
+        }
+        map
+    }
+
+    fn parents_of(map: &HashMap<u8, CellCoverage>, value: u8) -> Vec<CellIndex> {
+        map.get(&value)
+            .map(|coverage| coverage.compacted_iter().collect())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_coarsen_rules() {
+        let map = map_with_mixed_children();
+        let parent = parent_cell();
+
+        let majority = coarsen(&map, Resolution::Eight, &Majority);
+        assert_eq!(parents_of(&majority, 1), vec![parent]);
+        assert!(parents_of(&majority, 2).is_empty());
+
+        let mode = coarsen(&map, Resolution::Eight, &Mode);
+        assert_eq!(parents_of(&mode, 1), vec![parent]);
+
+        let all = coarsen(&map, Resolution::Eight, &All);
+        assert!(all.is_empty());
+
+        let any = coarsen(&map, Resolution::Eight, &Any);
+        assert_eq!(parents_of(&any, 1), vec![parent]);
+        assert_eq!(parents_of(&any, 2), vec![parent]);
+
+        // (5 * 1 + 1 * 2) / 6
+        let mean = coarsen(
+            &map,
+            Resolution::Eight,
+            &Mean(|m: f64| (m * 6.0).round() as u8),
+        );
+        assert_eq!(parents_of(&mean, 7), vec![parent]);
+    }
+
+    #[test]
+    fn test_coarsen_keeps_coarser_cells() {
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        map.entry(1).or_default().insert(parent_cell());
+        let coarsened = coarsen(&map, Resolution::Eight, &Majority);
+        assert_eq!(parents_of(&coarsened, 1), vec![parent_cell()]);
+    }
+
+    #[test]
+    fn test_pyramid() {
+        let arr = ndarray::Array2::from_shape_fn((60, 60), |(r, c)| ((r / 20) * 3 + c / 20) as u8);
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u8), &transform, AxisOrder::YX);
+        let map = conv.to_h3(Resolution::Eight, false).unwrap();
+
+        let levels = pyramid(
+            &map,
+            Resolution::range(Resolution::Five, Resolution::Eight),
+            &Mode,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            levels.keys().copied().collect::<Vec<_>>(),
+            Resolution::range(Resolution::Five, Resolution::Eight).collect::<Vec<_>>()
+        );
+        for (resolution, level) in levels.iter() {
+            assert!(!level.is_empty());
+            assert!(level.keys().all(|value| map.contains_key(value)));
+            for coverage in level.values() {
+                assert!(
+                    coverage
+                        .compacted_iter()
+                        .all(|cell| cell.resolution() == *resolution)
+                );
+            }
+        }
+        // the finest level is the unchanged input
+        assert_eq!(
+            levels[&Resolution::Eight]
+                .values()
+                .map(CellCoverage::len)
+                .sum::<usize>(),
+            map.values().map(CellCoverage::len).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn test_pyramid_matches_coarsen() {
+        // in four of the children at res 8, four of the seven grandchildren at res 9 are 1,
+        // all other grandchildren are 2. Four of the children have a majority of 1, but
+        // only 16 of the 49 grandchildren are 1.
+        let grandparent = parent_cell().parent(Resolution::Seven).unwrap();
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        for (i, child) in grandparent.children(Resolution::Eight).enumerate() {
+            for (j, grandchild) in child.children(Resolution::Nine).enumerate() {
+                let value = if i < 4 && j < 4 { 1 } else { 2 };
+                map.entry(value).or_default().insert(grandchild);
+            }
+        }
+
+        let resolutions = [Resolution::Seven, Resolution::Eight];
+        for compact in [false, true] {
+            let levels = pyramid(&map, resolutions, &Majority, compact).unwrap();
+            for resolution in resolutions {
+                let coarsened = map.coarsen(resolution, &Majority, compact).unwrap();
+                assert_eq!(levels[&resolution].len(), coarsened.len());
+                for value in coarsened.keys() {
+                    assert_eq!(
+                        parents_of(&levels[&resolution], *value),
+                        parents_of(&coarsened, *value)
+                    );
+                }
+            }
+        }
+        let levels = pyramid(&map, resolutions, &Majority, false).unwrap();
+        assert_eq!(parents_of(&levels[&Resolution::Seven], 1), vec![]);
+        assert_eq!(
+            parents_of(&levels[&Resolution::Seven], 2),
+            vec![grandparent]
+        );
+    }
+
+    #[test]
+    fn test_categorical_tie_break_and_min_coverage() {
+        // three children are 1, three children are 2 and one child has no value.
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        for (i, child) in parent_cell().children(Resolution::Nine).enumerate() {
+            match i {
+                0..3 => map.entry(1).or_default().insert(child),
+                3..6 => map.entry(2).or_default().insert(child),
+                _ => (),
+            }
+        }
+        let parent = parent_cell();
+
+        let coarsened = map
+            .coarsen(Resolution::Eight, &Categorical::default(), true)
+            .unwrap();
+        assert!(coarsened.is_empty());
+
+        let lowest = Categorical {
+            tie_break: TieBreak::Lowest,
+            ..Default::default()
+        };
+        let coarsened = map.coarsen(Resolution::Eight, &lowest, true).unwrap();
+        assert_eq!(parents_of(&coarsened, 1), vec![parent]);
+        assert!(parents_of(&coarsened, 2).is_empty());
+
+        let highest = Categorical {
+            tie_break: TieBreak::Highest,
+            ..Default::default()
+        };
+        let coarsened = map.coarsen(Resolution::Eight, &highest, true).unwrap();
+        assert_eq!(parents_of(&coarsened, 2), vec![parent]);
+
+        // 6 of 7 children have a value
+        let min_coverage = Categorical {
+            tie_break: TieBreak::Lowest,
+            min_coverage: 0.9,
+        };
+        let coarsened = map.coarsen(Resolution::Eight, &min_coverage, true).unwrap();
+        assert!(coarsened.is_empty());
+        let min_coverage = Categorical {
+            tie_break: TieBreak::Lowest,
+            min_coverage: 0.8,
+        };
+        let coarsened = map.coarsen(Resolution::Eight, &min_coverage, true).unwrap();
+        assert_eq!(parents_of(&coarsened, 1), vec![parent]);
+    }
+}
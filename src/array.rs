@@ -1,20 +1,36 @@
 use std::cmp::min;
 use std::hash::Hash;
+#[cfg(feature = "async")]
+use std::ops::ControlFlow;
+#[cfg(any(test, feature = "bench-internals"))]
+use std::ops::Range;
+use std::path::PathBuf;
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
 
 use ahash::HashMap;
-use geo::{AffineOps, AffineTransform, MapCoords};
-use geo_types::{Coord, Rect, point};
-use h3o::geom::{ContainmentMode, Tiler, TilerBuilder};
-use h3o::{LatLng, Resolution};
-use ndarray::{ArrayView2, Axis, s};
+use geo::{AffineOps, AffineTransform, Intersects, MapCoords};
+use geo_types::{Coord, Polygon, Rect, coord, point};
+use h3o::geom::{ContainmentMode, TilerBuilder};
+use h3o::{CellIndex, LatLng, Resolution};
+#[cfg(any(test, feature = "bench-internals"))]
+use ndarray::Axis;
+use ndarray::{ArrayView2, s};
+use num_traits::ToPrimitive;
 
 #[cfg(feature = "rayon")]
 use rayon::prelude::*;
 
 use tracing::debug;
 
-use crate::resolution::ResolutionSearchMode;
-use crate::util::split_rect_at_antimeridian;
+use crate::cell_value_map::{CellValueMap, CellValueStorage};
+use crate::resolution::{ResolutionRange, ResolutionSearchMode, pixel_area_m2};
+use crate::sampling::{Sampler, Sampling};
+use crate::sphere::AreaOnSphere;
+use crate::spill::SpillingCoverageMap;
+use crate::threads::Threads;
+use crate::transform::PixelRegistration;
+use crate::util::{SplittedPolygon, clip_to_valid_latitudes, split_polygon_at_antimeridian};
 use crate::{AxisOrder, CellCoverage, error::Error};
 
 #[cfg(feature = "rayon")]
@@ -27,89 +43,267 @@ pub trait ArrayValue: Sized + PartialEq + Eq + Hash {}
 #[cfg(not(feature = "rayon"))]
 impl<T> ArrayValue for T where T: Sized + PartialEq + Eq + Hash {}
 
-fn find_continuous_chunks_along_axis<T>(
+/// Find rects in the array containing any values except the `nodata_value`.
+///
+/// The array is divided into square blocks of `block_size` elements, and the blocks containing
+/// data are marked in an occupancy bitmap. Horizontally adjacent occupied blocks are merged into
+/// runs, and runs spanning the same columns in consecutive rows of blocks are merged into rects.
+/// The rects grow to at most `max_size` elements along both axes and are finally shrunk to the
+/// extent of the data they contain.
+///
+/// In contrast to only splitting at completely empty rows and columns, this yields tight rects
+/// for diagonal and scattered data. The max coordinates of the returned rects are exclusive.
+fn find_rects_containing_data<T>(
     a: &ArrayView2<T>,
-    axis: usize,
     nodata_value: &T,
-) -> Vec<(usize, usize)>
+    axis_order: &AxisOrder,
+    block_size: usize,
+    max_size: usize,
+) -> Vec<Rect<usize>>
 where
     T: ArrayValue,
 {
-    let mut chunks = Vec::new();
-    let mut current_chunk_start: Option<usize> = None;
+    let x_size = a.shape()[axis_order.x_axis()];
+    let y_size = a.shape()[axis_order.y_axis()];
+    let n_blocks_x = x_size.div_ceil(block_size);
+    let n_blocks_y = y_size.div_ceil(block_size);
+    let max_blocks = (max_size / block_size).max(1);
+
+    #[cfg(feature = "rayon")]
+    let block_rows = (0..n_blocks_y).into_par_iter();
+
+    #[cfg(not(feature = "rayon"))]
+    let block_rows = 0..n_blocks_y;
+
+    let occupancy = block_rows
+        .map(|block_y| {
+            let y_range = (block_y * block_size)..min(y_size, (block_y + 1) * block_size);
+            (0..n_blocks_x)
+                .map(|block_x| {
+                    let x_range = (block_x * block_size)..min(x_size, (block_x + 1) * block_size);
+                    let block = match axis_order {
+                        AxisOrder::XY => a.slice(s![x_range, y_range.clone()]),
+                        AxisOrder::YX => a.slice(s![y_range.clone(), x_range]),
+                    };
+                    block.iter().any(|v| v != nodata_value)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let to_rect = |(block_x_start, block_x_end, block_y_start): (usize, usize, usize),
+                   block_y_end: usize| {
+        Rect::new(
+            Coord {
+                x: block_x_start * block_size,
+                y: block_y_start * block_size,
+            },
+            Coord {
+                x: min(x_size, block_x_end * block_size),
+                y: min(y_size, block_y_end * block_size),
+            },
+        )
+    };
 
-    for (r0pos, r0) in a.axis_iter(Axis(axis)).enumerate() {
-        if r0.iter().any(|v| v != nodata_value) {
-            if current_chunk_start.is_none() {
-                current_chunk_start = Some(r0pos);
+    let mut rects = Vec::new();
+    // rects still growing downwards as (block_x_start, block_x_end, block_y_start)
+    let mut open = Vec::<(usize, usize, usize)>::new();
+    for (block_y, row) in occupancy.iter().enumerate() {
+        let mut still_open = Vec::with_capacity(open.len());
+        let mut block_x = 0;
+        while block_x < n_blocks_x {
+            if !row[block_x] {
+                block_x += 1;
+                continue;
+            }
+            let run_start = block_x;
+            while block_x < n_blocks_x && row[block_x] && block_x - run_start < max_blocks {
+                block_x += 1;
+            }
+            let run = (run_start, block_x);
+
+            match open.iter().position(|&(start, end, block_y_start)| {
+                (start, end) == run && block_y - block_y_start < max_blocks
+            }) {
+                Some(pos) => still_open.push(open.swap_remove(pos)),
+                None => still_open.push((run.0, run.1, block_y)),
             }
-        } else if let Some(begin) = current_chunk_start {
-            chunks.push((begin, r0pos - 1));
-            current_chunk_start = None;
         }
+        rects.extend(open.drain(..).map(|rect| to_rect(rect, block_y)));
+        open = still_open;
     }
-    if let Some(begin) = current_chunk_start {
-        chunks.push((begin, a.shape()[axis] - 1));
+    rects.extend(open.into_iter().map(|rect| to_rect(rect, n_blocks_y)));
+
+    #[cfg(feature = "rayon")]
+    let rects = rects.into_par_iter();
+
+    #[cfg(not(feature = "rayon"))]
+    let rects = rects.into_iter();
+
+    rects
+        .map(|rect| shrink_to_data(a, nodata_value, axis_order, rect))
+        .collect()
+}
+
+/// Shrink a rect containing data to the bounding box of the values except the `nodata_value`.
+fn shrink_to_data<T>(
+    a: &ArrayView2<T>,
+    nodata_value: &T,
+    axis_order: &AxisOrder,
+    rect: Rect<usize>,
+) -> Rect<usize>
+where
+    T: ArrayValue,
+{
+    let (mut min_x, mut min_y) = (rect.max().x, rect.max().y);
+    let (mut max_x, mut max_y) = (rect.min().x, rect.min().y);
+    for y in rect.min().y..rect.max().y {
+        for x in rect.min().x..rect.max().x {
+            let value = match axis_order {
+                AxisOrder::XY => &a[(x, y)],
+                AxisOrder::YX => &a[(y, x)],
+            };
+            if value != nodata_value {
+                min_x = min_x.min(x);
+                min_y = min_y.min(y);
+                max_x = max_x.max(x + 1);
+                max_y = max_y.max(y + 1);
+            }
+        }
     }
-    chunks
+    Rect::new(Coord { x: min_x, y: min_y }, Coord { x: max_x, y: max_y })
 }
 
-/// Find all boxes in the array where there are any values except the `nodata_value`
+/// Find rects in the array containing any values except the `nodata_value` by only splitting
+/// at completely empty rows and columns.
 ///
-/// This implementation is far from perfect and often recognizes multiple smaller
-/// clusters as one as its based on completely empty columns and rows, but it is probably
-/// sufficient for the purpose to reduce the number of hexagons
-/// to be generated when dealing with fragmented/sparse datasets.
-fn find_boxes_containing_data<T>(
+/// This was used before [`find_rects_containing_data`] and often recognizes multiple smaller
+/// clusters as one. It is only kept to compare both in the tests and the benchmarks. The rects
+/// have at most `max_size` elements along each axis. The max coordinates of the returned rects
+/// are exclusive.
+#[cfg(any(test, feature = "bench-internals"))]
+fn find_rects_between_empty_lines<T>(
     a: &ArrayView2<T>,
     nodata_value: &T,
     axis_order: &AxisOrder,
+    max_size: usize,
 ) -> Vec<Rect<usize>>
 where
     T: ArrayValue,
 {
-    find_continuous_chunks_along_axis(a, axis_order.x_axis(), nodata_value)
-        .into_iter()
-        .flat_map(|chunk_x_raw_indexes| {
-            let sv = {
-                let x_raw_range = chunk_x_raw_indexes.0..=chunk_x_raw_indexes.1;
-                match axis_order {
-                    AxisOrder::XY => a.slice(s![x_raw_range, ..]),
-                    AxisOrder::YX => a.slice(s![.., x_raw_range]),
+    let slice = |x_range: Range<usize>, y_range: Range<usize>| match axis_order {
+        AxisOrder::XY => a.slice(s![x_range, y_range]),
+        AxisOrder::YX => a.slice(s![y_range, x_range]),
+    };
+    let x_size = a.shape()[axis_order.x_axis()];
+    let full_y = 0..a.shape()[axis_order.y_axis()];
+
+    let mut rects = Vec::new();
+    for x_start in (0..x_size).step_by(max_size.max(1)) {
+        let x_tile = x_start..min(x_size, x_start + max_size);
+        let tile_view = slice(x_tile, full_y.clone());
+        for x_chunk in continuous_chunks(&tile_view, axis_order.x_axis(), nodata_value) {
+            let x_chunk = (x_start + x_chunk.start)..(x_start + x_chunk.end);
+            let x_chunk_view = slice(x_chunk.clone(), full_y.clone());
+            for y_chunk in continuous_chunks(&x_chunk_view, axis_order.y_axis(), nodata_value) {
+                // one more iteration along the x axis to get the columns for these rows
+                let view = slice(x_chunk.clone(), y_chunk.clone());
+                for x_range in continuous_chunks(&view, axis_order.x_axis(), nodata_value) {
+                    let x_range = (x_chunk.start + x_range.start)..(x_chunk.start + x_range.end);
+                    for y_start in y_chunk.clone().step_by(max_size) {
+                        rects.push(Rect::new(
+                            Coord {
+                                x: x_range.start,
+                                y: y_start,
+                            },
+                            Coord {
+                                x: x_range.end,
+                                y: min(y_chunk.end, y_start + max_size),
+                            },
+                        ));
+                    }
                 }
-            };
-            find_continuous_chunks_along_axis(&sv, axis_order.y_axis(), nodata_value)
-                .into_iter()
-                .flat_map(move |chunks_y_raw_indexes| {
-                    let sv2 = {
-                        let x_raw_range = 0..=(chunk_x_raw_indexes.1 - chunk_x_raw_indexes.0);
-                        let y_raw_range = chunks_y_raw_indexes.0..=chunks_y_raw_indexes.1;
-                        match axis_order {
-                            AxisOrder::XY => sv.slice(s![x_raw_range, y_raw_range]),
-                            AxisOrder::YX => sv.slice(s![y_raw_range, x_raw_range]),
-                        }
-                    };
+            }
+        }
+    }
+    rects
+}
 
-                    // one more iteration along axis 0 to get the specific range for that axis 1 range
-                    find_continuous_chunks_along_axis(&sv2, axis_order.x_axis(), nodata_value)
-                        .into_iter()
-                        .map(move |chunks_x_indexes| {
-                            Rect::new(
-                                Coord {
-                                    x: chunks_x_indexes.0 + chunk_x_raw_indexes.0,
-                                    y: chunks_y_raw_indexes.0,
-                                },
-                                Coord {
-                                    x: chunks_x_indexes.1 + chunk_x_raw_indexes.0,
-                                    y: chunks_y_raw_indexes.1,
-                                },
-                            )
-                        })
-                })
-        })
-        .collect::<Vec<_>>()
+/// Ranges of consecutive rows or columns along `axis` containing any values except
+/// the `nodata_value`.
+#[cfg(any(test, feature = "bench-internals"))]
+fn continuous_chunks<T>(a: &ArrayView2<T>, axis: usize, nodata_value: &T) -> Vec<Range<usize>>
+where
+    T: ArrayValue,
+{
+    let mut chunks = Vec::new();
+    let mut current_chunk_start = None;
+    for (pos, lane) in a.axis_iter(Axis(axis)).enumerate() {
+        if lane.iter().any(|v| v != nodata_value) {
+            current_chunk_start.get_or_insert(pos);
+        } else if let Some(start) = current_chunk_start.take() {
+            chunks.push(start..pos);
+        }
+    }
+    if let Some(start) = current_chunk_start {
+        chunks.push(start..a.shape()[axis]);
+    }
+    chunks
+}
+
+/// How the rects of an array containing data are found.
+///
+/// Only used to compare the strategies in the tests and, with the `bench-internals` feature,
+/// in the benchmarks.
+#[cfg(any(test, feature = "bench-internals"))]
+#[doc(hidden)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SparseDetection {
+    /// Merge the blocks of an occupancy bitmap, see [`find_rects_containing_data`].
+    #[default]
+    BlockOccupancy,
+
+    /// Split at completely empty rows and columns, see [`find_rects_between_empty_lines`].
+    EmptyLines,
+}
+
+/// How parts of a raster located beyond the poles are handled.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LatitudeOverflow {
+    /// Fail with [`Error::LatitudeOutOfRange`] when the transform yields latitudes outside
+    /// of [-90, 90].
+    #[default]
+    Reject,
+
+    /// Clamp the raster to latitudes within [-90, 90]. Pixels located completely beyond the
+    /// poles do not get converted.
+    Clamp,
 }
 
+/// Tolerance for latitudes beyond the poles caused by the limited precision of the transform.
+const LATITUDE_TOLERANCE: f64 = 1e-9;
+
+/// Number of cells each rect of the array is expected to contain during the conversion.
+const TARGET_CELLS_PER_RECT: f64 = 10_000.0;
+
+/// Bounds of the edge length of the rects the array gets split into.
+const MIN_RECT_SIZE: usize = 10;
+const MAX_RECT_SIZE: usize = 2_000;
+
+/// Maximum number of degrees of longitude spanned by a single rect.
+const MAX_RECT_LNG_SPAN: f64 = 90.0;
+
+/// Minimum number of rows of rects the array is split into for adaptive conversions, so the
+/// resolution can follow the changing pixel size.
+const MIN_ADAPTIVE_RECT_ROWS: usize = 10;
+
+/// Number of pixels the windows are enlarged by on each side when generating their cells.
+///
+/// Each cell is owned by the rect containing the pixel under its centroid, so no cell is
+/// generated by more than one rect. The margin only makes sure that cells with their
+/// centroid on the edge of a rect are not missed due to the limited floating point precision.
+const WINDOW_MARGIN: f64 = 0.5;
+
 /// Converts a two-dimensional [`ndarray::ArrayView2`] to H3 cells.
 ///
 /// The implementation tries to skip over regions with only nodata values.
@@ -121,6 +315,17 @@ where
     nodata_value: &'a Option<T>,
     transform: &'a AffineTransform<f64>,
     axis_order: AxisOrder,
+    pixel_registration: PixelRegistration,
+    latitude_overflow: LatitudeOverflow,
+    wrap_longitude: bool,
+    threads: Threads,
+    memory_budget: Option<usize>,
+    spill_directory: Option<PathBuf>,
+    #[cfg(any(test, feature = "bench-internals"))]
+    sparse_detection: SparseDetection,
+
+    /// `transform` referring to the upper left corners of the pixels
+    area_transform: AffineTransform<f64>,
 }
 
 impl<'a, T> H3Converter<'a, T>
@@ -138,77 +343,189 @@ where
             nodata_value,
             transform,
             axis_order,
+            pixel_registration: PixelRegistration::default(),
+            latitude_overflow: LatitudeOverflow::default(),
+            wrap_longitude: false,
+            threads: Threads::default(),
+            memory_budget: None,
+            spill_directory: None,
+            #[cfg(any(test, feature = "bench-internals"))]
+            sparse_detection: SparseDetection::default(),
+            area_transform: *transform,
+        }
+    }
+
+    /// Set how the transform refers to the pixels. Defaults to [`PixelRegistration::Area`].
+    pub fn with_pixel_registration(mut self, pixel_registration: PixelRegistration) -> Self {
+        self.pixel_registration = pixel_registration;
+        self.area_transform = pixel_registration.to_area_transform(self.transform);
+        self
+    }
+
+    /// Set how parts of the raster located beyond the poles are handled. Defaults to
+    /// [`LatitudeOverflow::Reject`].
+    pub fn with_latitude_overflow(mut self, latitude_overflow: LatitudeOverflow) -> Self {
+        self.latitude_overflow = latitude_overflow;
+        self
+    }
+
+    /// Treat the raster as spanning the whole globe with its columns wrapping around, as with
+    /// rasters covering longitudes 0..360 or -180..180. Cells at the seam get their values from
+    /// the column at the opposite edge, and interpolating samplings use the pixels on both
+    /// sides of the seam.
+    ///
+    /// This requires a north-up transform with the columns spanning exactly 360 degrees,
+    /// otherwise the conversion fails with [`Error::NotGlobal`].
+    pub fn with_longitude_wrap(mut self, wrap_longitude: bool) -> Self {
+        self.wrap_longitude = wrap_longitude;
+        self
+    }
+
+    /// Run the parallel stages of the conversion on a dedicated thread pool with `num_threads`
+    /// threads instead of the global rayon thread pool. The pool gets created for each
+    /// conversion, use [`H3Converter::with_thread_pool`] to reuse a pool.
+    ///
+    /// Without the `rayon` feature the conversion is single-threaded and this setting
+    /// is ignored.
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.threads = Threads::Count(num_threads);
+        self
+    }
+
+    /// Run the parallel stages of the conversion on `pool` instead of the global rayon
+    /// thread pool.
+    #[cfg(feature = "rayon")]
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.threads = Threads::Pool(pool);
+        self
+    }
+
+    /// Limit the memory used for the cells while merging the results of the individual
+    /// rects in [`H3Converter::to_h3`] and [`H3Converter::to_h3_adaptive`] to about
+    /// `memory_budget` bytes.
+    ///
+    /// Beyond the budget, the cells of each value are written to temporary files as sorted
+    /// runs, which get merged when finalizing the conversion. When compacting, the merged
+    /// cells are compacted while they are streamed in, so only the compacted result needs to
+    /// fit in memory. Without compacting the complete result is held in memory in the end.
+    ///
+    /// The budget does not include the memory used by the rects being converted at a time.
+    pub fn with_memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// Set the directory for the temporary files created when exceeding the memory budget.
+    /// Defaults to [`std::env::temp_dir`].
+    pub fn with_spill_directory(mut self, spill_directory: impl Into<PathBuf>) -> Self {
+        self.spill_directory = Some(spill_directory.into());
+        self
+    }
+
+    /// Set how the rects of the array containing data are found. Only meant for the benchmarks.
+    #[cfg(feature = "bench-internals")]
+    #[doc(hidden)]
+    pub fn with_sparse_detection(mut self, sparse_detection: SparseDetection) -> Self {
+        self.sparse_detection = sparse_detection;
+        self
+    }
+
+    pub(crate) fn with_threads(mut self, threads: Threads) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn threads(&self) -> &Threads {
+        &self.threads
+    }
+
+    /// Check the extent of the raster is valid for the longitude wrap.
+    fn check_longitude_wrap(&self) -> Result<(), Error> {
+        if !self.wrap_longitude {
+            return Ok(());
+        }
+        let x_size = self.arr.shape()[self.axis_order.x_axis()] as f64;
+        let t = &self.area_transform;
+        if t.b() == 0.0 && t.d() == 0.0 && ((t.a() * x_size).abs() - 360.0).abs() < 1e-6 {
+            Ok(())
+        } else {
+            Err(Error::NotGlobal)
+        }
+    }
+
+    /// Check the extent of the raster according to the [`LatitudeOverflow`] and the
+    /// longitude wrap.
+    fn check_extent(&self) -> Result<(), Error> {
+        self.check_longitude_wrap()?;
+        if self.latitude_overflow == LatitudeOverflow::Clamp {
+            return Ok(());
+        }
+        let x_size = self.arr.shape()[self.axis_order.x_axis()] as f64;
+        let y_size = self.arr.shape()[self.axis_order.y_axis()] as f64;
+        let footprint = Rect::new((0.0, 0.0), (x_size, y_size))
+            .to_polygon()
+            .affine_transform(&self.area_transform);
+        match footprint
+            .exterior()
+            .coords()
+            .find(|c| c.y.abs() > 90.0 + LATITUDE_TOLERANCE)
+        {
+            Some(c) => Err(Error::LatitudeOutOfRange(c.y)),
+            None => Ok(()),
         }
     }
 
     /// Find the H3 resolution closest to the size of a pixel in an array,
+    ///
+    /// The [`LatitudeOverflow`] is not checked here, parts of the raster located beyond the
+    /// poles are left out when determining the pixel size.
     pub fn nearest_h3_resolution(
         &self,
         search_mode: ResolutionSearchMode,
     ) -> Result<Resolution, Error> {
-        search_mode.nearest_h3_resolution(
+        self.check_longitude_wrap()?;
+        search_mode.nearest_h3_resolution_with_registration(
             [self.arr.dim().0, self.arr.dim().1],
             self.transform,
             &self.axis_order,
+            self.pixel_registration,
         )
     }
 
-    fn chunk_rects_with_data_nodata(
+    /// Find the range of H3 resolutions suitable for the pixels of the array by
+    /// sampling `n_bands` bands of rows.
+    ///
+    /// See [`ResolutionSearchMode::h3_resolution_range`]. As with
+    /// [`H3Converter::nearest_h3_resolution`], parts of the raster located beyond the poles are
+    /// left out regardless of the [`LatitudeOverflow`].
+    pub fn h3_resolution_range(
         &self,
-        rect_size: usize,
-        nodata: &T,
-        axis_x_chunk_i: usize,
-        axis_x_chunk: ArrayView2<T>,
-    ) -> Vec<Rect<usize>> {
-        let mut rects = Vec::new();
-        for chunk_x_rect in find_boxes_containing_data(&axis_x_chunk, nodata, &self.axis_order) {
-            let offset_x = (axis_x_chunk_i * rect_size) + chunk_x_rect.min().x;
-            let chunk_rect_view = {
-                let x_range = chunk_x_rect.min().x..chunk_x_rect.max().x;
-                let y_range = chunk_x_rect.min().y..chunk_x_rect.max().y;
-                match self.axis_order {
-                    AxisOrder::XY => axis_x_chunk.slice(s![x_range, y_range]),
-                    AxisOrder::YX => axis_x_chunk.slice(s![y_range, x_range]),
-                }
-            };
-            rects.extend(
-                chunk_rect_view
-                    .axis_chunks_iter(Axis(self.axis_order.y_axis()), rect_size)
-                    .enumerate()
-                    .map(|(axis_y_chunk_i, axis_y_chunk)| {
-                        let offset_y = (axis_y_chunk_i * rect_size) + chunk_x_rect.min().y;
-
-                        // the window in array coordinates
-                        Rect::new(
-                            Coord {
-                                x: offset_x,
-                                y: offset_y,
-                            },
-                            // add 1 to the max coordinate to include the whole last pixel
-                            Coord {
-                                x: (offset_x + axis_y_chunk.shape()[self.axis_order.x_axis()] + 1),
-                                y: (offset_y + axis_y_chunk.shape()[self.axis_order.y_axis()] + 1),
-                            },
-                        )
-                    }),
-            )
-        }
-        rects
+        search_mode: ResolutionSearchMode,
+        n_bands: usize,
+    ) -> Result<ResolutionRange, Error> {
+        self.check_longitude_wrap()?;
+        search_mode.h3_resolution_range(
+            [self.arr.dim().0, self.arr.dim().1],
+            self.transform,
+            &self.axis_order,
+            self.pixel_registration,
+            n_bands,
+        )
     }
 
     fn rects_with_data_with_nodata(&self, rect_size: usize, nodata: &T) -> Vec<Rect<usize>> {
-        let iter = self
-            .arr
-            .axis_chunks_iter(Axis(self.axis_order.x_axis()), rect_size);
-
-        #[cfg(feature = "rayon")]
-        let iter = iter.into_par_iter();
-
-        iter.enumerate()
-            .flat_map(|(axis_x_chunk_i, axis_x_chunk)| {
-                self.chunk_rects_with_data_nodata(rect_size, nodata, axis_x_chunk_i, axis_x_chunk)
-            })
-            .collect()
+        #[cfg(any(test, feature = "bench-internals"))]
+        if self.sparse_detection == SparseDetection::EmptyLines {
+            return find_rects_between_empty_lines(self.arr, nodata, &self.axis_order, rect_size);
+        }
+        find_rects_containing_data(
+            self.arr,
+            nodata,
+            &self.axis_order,
+            (rect_size / 16).clamp(4, 16),
+            rect_size,
+        )
     }
 
     fn rects_with_data_without_nodata(&self, rect_size: usize) -> Vec<Rect<usize>> {
@@ -249,53 +566,325 @@ where
         h3_resolution: Resolution,
         compact: bool,
     ) -> Result<HashMap<&'a T, CellCoverage>, Error> {
-        let inverse_transform = self
-            .transform
-            .inverse()
-            .ok_or(Error::TransformNotInvertible)?;
+        self.threads.install(|| {
+            self.convert_rects(
+                self.rect_size(h3_resolution),
+                |_| Ok(h3_resolution),
+                compact,
+            )
+        })
+    }
 
-        let rect_size = (self.arr.shape()[self.axis_order.x_axis()] / 10).clamp(10, 100);
-        let rects = self.rects_with_data(rect_size);
-        let n_rects = rects.len();
-        debug!(
-            "to_h3: found {} rects containing non-nodata values",
-            n_rects
-        );
+    /// Convert to a hashmap mapping raster values to their `CellCoverage`, choosing the
+    /// H3 resolution for each region of the array separately.
+    ///
+    /// The `search_mode` is evaluated for every rect the array is split into, so each rect
+    /// gets converted at the resolution matching its local pixel size. For global datasets
+    /// this avoids oversampling towards the poles. The returned `CellCoverage`s contain cells
+    /// of mixed resolutions; use [`CellCoverage::normalize`] to bring them to a single
+    /// target resolution.
+    ///
+    /// Cells of neighbouring rects converted at different resolutions may overlap.
+    pub fn to_h3_adaptive(
+        &self,
+        search_mode: ResolutionSearchMode,
+        compact: bool,
+    ) -> Result<HashMap<&'a T, CellCoverage>, Error> {
+        self.threads.install(|| {
+            let area_transform = &self.area_transform;
+            let y_size = self.arr.shape()[self.axis_order.y_axis()];
+            let rect_size = self
+                .rect_size(self.nearest_h3_resolution(search_mode)?)
+                .min((y_size / MIN_ADAPTIVE_RECT_ROWS).max(MIN_RECT_SIZE));
+            self.convert_rects(
+                rect_size,
+                |array_window| {
+                    let window = array_window.map_coords(|c| Coord::from((c.x as f64, c.y as f64)));
+                    search_mode.nearest_h3_resolution_for_window(window, area_transform)
+                },
+                compact,
+            )
+        })
+    }
 
-        #[cfg(feature = "rayon")]
-        let rects_iter = rects.into_par_iter();
+    /// Convert to a map of cells to the raster value under their centroid.
+    ///
+    /// In contrast to [`H3Converter::to_h3`] the cells are not grouped by their values.
+    pub fn to_cell_value_map(
+        &self,
+        h3_resolution: Resolution,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<&'a T>, Error> {
+        self.threads.install(|| {
+            self.check_extent()?;
+            let lookup = self.lookup()?;
 
-        #[cfg(not(feature = "rayon"))]
-        let rects_iter = rects.into_iter();
+            let rects = self.rects_with_data(self.rect_size(h3_resolution));
+            debug!(
+                "to_cell_value_map: found {} rects containing non-nodata values",
+                rects.len()
+            );
 
-        let chunk_h3_maps = rects_iter
-            .enumerate()
-            .map(|(array_window_i, array_window)| {
-                debug!(
-                    "to_h3: rect {}/{} with size {} x {}",
-                    array_window_i,
-                    n_rects,
-                    array_window.width(),
-                    array_window.height()
-                );
+            #[cfg(feature = "rayon")]
+            let rects_iter = rects.into_par_iter();
 
-                let window = array_window.map_coords(|c| Coord::from((c.x as f64, c.y as f64)));
-                // the window in geographical coordinates
-                let window_box = window.affine_transform(self.transform);
-
-                convert_array_window(
-                    self.arr,
-                    window_box,
-                    &inverse_transform,
-                    self.axis_order,
-                    self.nodata_value,
-                    TilerBuilder::new(h3_resolution)
-                        .containment_mode(ContainmentMode::ContainsCentroid)
-                        .build(),
-                    compact,
-                )
-            })
-            .collect::<Result<Vec<_>, _>>()?;
+            #[cfg(not(feature = "rayon"))]
+            let rects_iter = rects.into_iter();
+
+            let chunk_cell_values = rects_iter
+                .map(|array_window| {
+                    let mut cell_values = Vec::new();
+                    for splitted_window in self
+                        .window_polygon(&array_window)
+                        .map(split_polygon_at_antimeridian)
+                        .unwrap_or_default()
+                    {
+                        visit_window_cells(
+                            &lookup,
+                            &splitted_window,
+                            &array_window,
+                            h3_resolution,
+                            |cell, value| cell_values.push((cell, value)),
+                        )?;
+                    }
+                    Ok(cell_values)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            Ok(CellValueMap::from_cells(
+                chunk_cell_values.into_iter().flatten(),
+                storage,
+            ))
+        })
+    }
+
+    /// Convert a numeric raster to a [`CellValueMap`] at the given resolution, sampling the
+    /// raster at the cell centroids using `sampling`.
+    ///
+    /// Cells are created where the centroid is located on a pixel with data. With the
+    /// interpolating samplings, nodata pixels and pixels outside the raster are left out
+    /// of the neighbourhood used for the interpolation.
+    pub fn to_cell_value_map_numeric(
+        &self,
+        h3_resolution: Resolution,
+        sampling: Sampling,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<f64>, Error>
+    where
+        T: ToPrimitive,
+    {
+        self.threads.install(|| {
+            self.check_extent()?;
+            let sampler = Sampler::new(
+                self.arr,
+                self.nodata_value,
+                &self.area_transform,
+                self.axis_order,
+            )?
+            .with_longitude_wrap(self.wrap_longitude);
+            let lookup = self.lookup()?;
+
+            let rects = self.rects_with_data(self.rect_size(h3_resolution));
+            debug!(
+                "to_cell_value_map_numeric: found {} rects containing non-nodata values",
+                rects.len()
+            );
+
+            #[cfg(feature = "rayon")]
+            let rects_iter = rects.into_par_iter();
+
+            #[cfg(not(feature = "rayon"))]
+            let rects_iter = rects.into_iter();
+
+            let chunk_cell_values = rects_iter
+                .map(|array_window| {
+                    let mut cell_values = Vec::new();
+                    for splitted_window in self
+                        .window_polygon(&array_window)
+                        .map(split_polygon_at_antimeridian)
+                        .unwrap_or_default()
+                    {
+                        visit_window_cells(
+                            &lookup,
+                            &splitted_window,
+                            &array_window,
+                            h3_resolution,
+                            |cell, value| {
+                                let sampled = match sampling {
+                                    Sampling::Nearest => value.to_f64(),
+                                    _ => sampler.sample(cell, sampling),
+                                };
+                                if let Some(sampled) = sampled {
+                                    cell_values.push((cell, sampled));
+                                }
+                            },
+                        )?;
+                    }
+                    Ok(cell_values)
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+
+            Ok(CellValueMap::from_cells(
+                chunk_cell_values.into_iter().flatten(),
+                storage,
+            ))
+        })
+    }
+
+    /// Look up the raster values under the centroids of the given cells without converting
+    /// the whole raster.
+    ///
+    /// Cells located outside of the raster or on nodata are not contained in the returned map.
+    pub fn sample_cells(
+        &self,
+        cells: impl IntoIterator<Item = CellIndex>,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<&'a T>, Error> {
+        let cells = cells.into_iter().collect::<Vec<_>>();
+        self.threads.install(|| {
+            self.check_longitude_wrap()?;
+            let sampler = Sampler::new(
+                self.arr,
+                self.nodata_value,
+                &self.area_transform,
+                self.axis_order,
+            )?
+            .with_longitude_wrap(self.wrap_longitude);
+
+            #[cfg(feature = "rayon")]
+            let cells_iter = cells.into_par_iter();
+
+            #[cfg(not(feature = "rayon"))]
+            let cells_iter = cells.into_iter();
+
+            let cell_values = cells_iter
+                .filter_map(|cell| sampler.nearest(cell).map(|value| (cell, value)))
+                .collect::<Vec<_>>();
+            Ok(CellValueMap::from_cells(cell_values, storage))
+        })
+    }
+
+    /// Sample the numeric raster values at the given cells using the given `sampling`
+    /// without converting the whole raster.
+    ///
+    /// Cells located outside of the raster or only on nodata are not contained in the
+    /// returned map.
+    pub fn sample_cells_numeric(
+        &self,
+        cells: impl IntoIterator<Item = CellIndex>,
+        sampling: Sampling,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<f64>, Error>
+    where
+        T: ToPrimitive,
+    {
+        let cells = cells.into_iter().collect::<Vec<_>>();
+        self.threads.install(|| {
+            self.check_longitude_wrap()?;
+            let sampler = Sampler::new(
+                self.arr,
+                self.nodata_value,
+                &self.area_transform,
+                self.axis_order,
+            )?
+            .with_longitude_wrap(self.wrap_longitude);
+
+            #[cfg(feature = "rayon")]
+            let cells_iter = cells.into_par_iter();
+
+            #[cfg(not(feature = "rayon"))]
+            let cells_iter = cells.into_iter();
+
+            let cell_values = cells_iter
+                .filter_map(|cell| sampler.sample(cell, sampling).map(|value| (cell, value)))
+                .collect::<Vec<_>>();
+            Ok(CellValueMap::from_cells(cell_values, storage))
+        })
+    }
+
+    /// Edge length of the square rects the array gets split into for the conversion at
+    /// `h3_resolution`.
+    ///
+    /// The size is chosen so that each rect is expected to contain about
+    /// [`TARGET_CELLS_PER_RECT`] cells: coarse resolutions get large rects, so cells are not
+    /// generated over and over by many tiny rects, while rects at fine resolutions stay
+    /// small enough to not hold millions of cells. Rects never span more than
+    /// [`MAX_RECT_LNG_SPAN`] degrees of longitude, as the tiler considers polygons spanning
+    /// more than 180 degrees to cross the antimeridian.
+    fn rect_size(&self, h3_resolution: Resolution) -> usize {
+        let window = Rect::new(
+            Coord { x: 0.0, y: 0.0 },
+            Coord {
+                x: self.arr.shape()[self.axis_order.x_axis()] as f64,
+                y: self.arr.shape()[self.axis_order.y_axis()] as f64,
+            },
+        );
+        let Some(pixel_area) = pixel_area_m2(window, &self.area_transform) else {
+            return MIN_RECT_SIZE;
+        };
+        let pixels_per_rect = TARGET_CELLS_PER_RECT * h3_resolution.area_m2() / pixel_area;
+        if !pixels_per_rect.is_finite() {
+            return MIN_RECT_SIZE;
+        }
+        let lng_span_per_pixel = self.area_transform.a().abs() + self.area_transform.b().abs();
+        let max_rect_size = ((MAX_RECT_LNG_SPAN / lng_span_per_pixel) as usize).min(MAX_RECT_SIZE);
+        (pixels_per_rect.sqrt() as usize)
+            .min(max_rect_size)
+            .max(MIN_RECT_SIZE)
+    }
+
+    fn lookup(&self) -> Result<ArrayLookup<'a, T>, Error> {
+        Ok(ArrayLookup {
+            arr: self.arr,
+            nodata_value: self.nodata_value,
+            inverse_transform: self
+                .area_transform
+                .inverse()
+                .ok_or(Error::TransformNotInvertible)?,
+            axis_order: self.axis_order,
+            wrap_longitude: self.wrap_longitude,
+        })
+    }
+
+    /// The window of the array in geographical coordinates, enlarged by [`WINDOW_MARGIN`].
+    ///
+    /// For rotated or sheared transforms this is the transformed quadrilateral instead of
+    /// its bounding box. The window is clipped to the valid latitude range, `None` is returned
+    /// for windows located completely beyond the poles.
+    fn window_polygon(&self, array_window: &Rect<usize>) -> Option<Polygon<f64>> {
+        let window = Rect::new(
+            Coord {
+                x: array_window.min().x as f64 - WINDOW_MARGIN,
+                y: array_window.min().y as f64 - WINDOW_MARGIN,
+            },
+            Coord {
+                x: array_window.max().x as f64 + WINDOW_MARGIN,
+                y: array_window.max().y as f64 + WINDOW_MARGIN,
+            },
+        );
+        clip_to_valid_latitudes(window.to_polygon().affine_transform(&self.area_transform))
+    }
+
+    /// Split the array into rects of up to `rect_size` x `rect_size` elements and convert all
+    /// rects containing data using the resolution returned by `rect_resolution` for each of them.
+    fn convert_rects<F>(
+        &self,
+        rect_size: usize,
+        rect_resolution: F,
+        compact: bool,
+    ) -> Result<HashMap<&'a T, CellCoverage>, Error>
+    where
+        F: Fn(&Rect<usize>) -> Result<Resolution, Error> + Sync,
+    {
+        if let Some(memory_budget) = self.memory_budget {
+            return self.convert_rects_within_budget(
+                rect_size,
+                rect_resolution,
+                compact,
+                memory_budget,
+            );
+        }
+        let chunk_h3_maps = self.convert_rects_separately(rect_size, rect_resolution, compact)?;
 
         // combine the results from all chunks
         let mut h3_map = HashMap::default();
@@ -311,91 +900,499 @@ where
         finalize_chunk_map(&mut h3_map, compact)?;
         Ok(h3_map)
     }
-}
-
-fn convert_array_window<'a, T>(
-    arr: &'a ArrayView2<'a, T>,
-    window_box: Rect<f64>,
-    inverse_transform: &AffineTransform<f64>,
-    axis_order: AxisOrder,
-    nodata_value: &Option<T>,
-    tiler: Tiler,
-    compact: bool,
-) -> Result<HashMap<&'a T, CellCoverage>, Error>
-where
-    T: ArrayValue,
-{
-    let mut chunk_h3_map = HashMap::<&T, CellCoverage>::default();
 
-    for splitted_window_box in split_rect_at_antimeridian(window_box) {
-        let mut tiler = tiler.clone();
+    /// Like [`H3Converter::convert_rects`], but converts the rects in batches and merges
+    /// their results using a [`SpillingCoverageMap`] limited to `memory_budget`.
+    fn convert_rects_within_budget<F>(
+        &self,
+        rect_size: usize,
+        rect_resolution: F,
+        compact: bool,
+        memory_budget: usize,
+    ) -> Result<HashMap<&'a T, CellCoverage>, Error>
+    where
+        F: Fn(&Rect<usize>) -> Result<Resolution, Error> + Sync,
+    {
+        self.check_extent()?;
+        let lookup = self.lookup()?;
 
-        // h3 is only defined within -180 ... 180, so all boxes after the antimeridian split should be
-        // in this range.
-        debug_assert!(
-            splitted_window_box.rect.min().x >= -180.0 && splitted_window_box.rect.min().x <= 180.0
-        );
-        debug_assert!(
-            splitted_window_box.rect.max().x >= -180.0 && splitted_window_box.rect.max().x <= 180.0
+        let rects = self.rects_with_data(rect_size);
+        debug!(
+            "to_h3: found {} rects containing non-nodata values, merging within a budget of {} bytes",
+            rects.len(),
+            memory_budget
         );
 
-        tiler.add(splitted_window_box.rect.into())?;
-        for cell in tiler.into_coverage() {
-            // find the array element for the coordinate of the h3 index
-            let cell_centroid: Coord = LatLng::from(cell).into();
-            let arr_coord = {
-                // apply the x offset caused by the antimeridian split and transform
-                // to array coordinates
-                let transformed = point! {x: cell_centroid.x + splitted_window_box.difference_due_to_antimeridian_split, y:cell_centroid.y}
-                    .affine_transform(inverse_transform);
-                let (px, py) = (transformed.x().floor(), transformed.y().floor());
-                // `f64 as usize` saturates negatives to 0, which would silently
-                // assign boundary cells to the first pixel. Skip cells mapping
-                // outside the raster instead. (`arr.get` handles the upper bound.)
-                if px < 0.0 || py < 0.0 {
-                    continue;
-                }
-                match axis_order {
-                    AxisOrder::XY => [px as usize, py as usize],
-                    AxisOrder::YX => [py as usize, px as usize],
-                }
-            };
-            if let Some(value) = arr.get(arr_coord) {
-                if let Some(nodata) = nodata_value
-                    && nodata == value
-                {
-                    continue;
-                }
-                chunk_h3_map
-                    .entry(value)
-                    .or_insert_with(CellCoverage::default)
-                    .insert(cell);
-            }
-        }
-    }
+        let spill_directory = self
+            .spill_directory
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let mut h3_map = SpillingCoverageMap::new(memory_budget, &spill_directory);
 
-    // Do an early dedup/compact to free a bit of memory. When not compacting,
-    // skip the relatively expensive parent-removal here and defer it to the
-    // single final merge pass in `to_h3` to avoid repeating it for every chunk.
-    {
         #[cfg(feature = "rayon")]
-        let iter = chunk_h3_map.par_iter_mut();
+        let rects_per_batch = rayon::current_num_threads();
 
         #[cfg(not(feature = "rayon"))]
-        let mut iter = chunk_h3_map.iter_mut();
+        let rects_per_batch = 1;
 
-        iter.try_for_each(|(_, cellset)| {
-            if compact {
-                cellset.compact()
-            } else {
-                cellset.dedup(true, false);
-                Ok(())
+        for batch in rects.chunks(rects_per_batch) {
+            #[cfg(feature = "rayon")]
+            let batch_iter = batch.par_iter();
+
+            #[cfg(not(feature = "rayon"))]
+            let batch_iter = batch.iter();
+
+            let chunk_h3_maps = batch_iter
+                .map(|array_window| {
+                    self.convert_rect(&lookup, array_window, &rect_resolution, compact)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            for chunk_h3_map in chunk_h3_maps {
+                h3_map.append(chunk_h3_map, compact)?;
             }
-        })?;
+        }
+        h3_map.finish(compact)
     }
 
-    Ok(chunk_h3_map)
-}
+    /// Like [`H3Converter::convert_rects`], but returns the results of the rects without
+    /// combining them. Each cell is only contained in the result of a single rect.
+    fn convert_rects_separately<F>(
+        &self,
+        rect_size: usize,
+        rect_resolution: F,
+        compact: bool,
+    ) -> Result<Vec<HashMap<&'a T, CellCoverage>>, Error>
+    where
+        F: Fn(&Rect<usize>) -> Result<Resolution, Error> + Sync,
+    {
+        self.check_extent()?;
+        let lookup = self.lookup()?;
+
+        let rects = self.rects_with_data(rect_size);
+        let n_rects = rects.len();
+        debug!(
+            "to_h3: found {} rects containing non-nodata values",
+            n_rects
+        );
+
+        #[cfg(feature = "rayon")]
+        let rects_iter = rects.into_par_iter();
+
+        #[cfg(not(feature = "rayon"))]
+        let rects_iter = rects.into_iter();
+
+        rects_iter
+            .enumerate()
+            .map(|(array_window_i, array_window)| {
+                debug!("to_h3: rect {}/{}", array_window_i, n_rects);
+                self.convert_rect(&lookup, &array_window, &rect_resolution, compact)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    /// Convert the rects containing data at `h3_resolution` and pass the finalized result of
+    /// each rect to `visit` as soon as it is available. Each cell is only contained in the
+    /// result of a single rect.
+    ///
+    /// Failures are passed to `visit` as well. The conversion stops early when `visit`
+    /// returns [`ControlFlow::Break`]; with the `rayon` feature, rects already being converted
+    /// are still passed to `visit`.
+    #[cfg(feature = "async")]
+    pub(crate) fn for_each_rect<F>(&self, h3_resolution: Resolution, compact: bool, visit: F)
+    where
+        F: Fn(Result<HashMap<&'a T, CellCoverage>, Error>) -> ControlFlow<()> + Sync,
+    {
+        let lookup = match self.check_extent().and_then(|_| self.lookup()) {
+            Ok(lookup) => lookup,
+            Err(e) => {
+                let _ = visit(Err(e));
+                return;
+            }
+        };
+        let rect_resolution = |_: &Rect<usize>| Ok(h3_resolution);
+        let rects = self.rects_with_data(self.rect_size(h3_resolution));
+
+        #[cfg(feature = "rayon")]
+        let rects_iter = rects.into_par_iter();
+
+        #[cfg(not(feature = "rayon"))]
+        let mut rects_iter = rects.into_iter();
+
+        let _ = rects_iter.try_for_each(|array_window| {
+            visit(
+                self.convert_rect(&lookup, &array_window, &rect_resolution, compact)
+                    .and_then(|mut h3_map| {
+                        finalize_chunk_map(&mut h3_map, compact)?;
+                        Ok(h3_map)
+                    }),
+            )
+        });
+    }
+
+    /// Convert a single rect of the array using the resolution returned by `rect_resolution`.
+    fn convert_rect<F>(
+        &self,
+        lookup: &ArrayLookup<'a, T>,
+        array_window: &Rect<usize>,
+        rect_resolution: &F,
+        compact: bool,
+    ) -> Result<HashMap<&'a T, CellCoverage>, Error>
+    where
+        F: Fn(&Rect<usize>) -> Result<Resolution, Error>,
+    {
+        let Some(window) = self.window_polygon(array_window) else {
+            return Ok(HashMap::default());
+        };
+        let h3_resolution = rect_resolution(array_window)?;
+        debug!(
+            "to_h3: rect with size {} x {} at resolution {}",
+            array_window.width(),
+            array_window.height(),
+            h3_resolution
+        );
+
+        convert_array_window(lookup, window, array_window, h3_resolution, compact)
+    }
+}
+
+/// The array together with everything required to look up its elements by geographical
+/// coordinates.
+struct ArrayLookup<'a, T> {
+    arr: &'a ArrayView2<'a, T>,
+    nodata_value: &'a Option<T>,
+    inverse_transform: AffineTransform<f64>,
+    axis_order: AxisOrder,
+
+    /// Let the columns wrap around, as for rasters spanning the whole globe.
+    wrap_longitude: bool,
+}
+
+impl<'a, T> ArrayLookup<'a, T>
+where
+    T: ArrayValue,
+{
+    /// Find the coordinate of the array element located under the centroid of `cell`.
+    ///
+    /// `lng_offset` is the x offset caused by the antimeridian split, it is applied before
+    /// transforming to array coordinates. The coordinate may be located beyond the upper
+    /// bounds of the array.
+    fn coord_at_cell_centroid(&self, cell: CellIndex, lng_offset: f64) -> Option<Coord<usize>> {
+        let cell_centroid: Coord = LatLng::from(cell).into();
+        let transformed = point! {x: cell_centroid.x + lng_offset, y: cell_centroid.y}
+            .affine_transform(&self.inverse_transform);
+        let (mut px, py) = (transformed.x().floor(), transformed.y().floor());
+        if self.wrap_longitude {
+            px = px.rem_euclid(self.arr.shape()[self.axis_order.x_axis()] as f64);
+        }
+        // `f64 as usize` saturates negatives to 0, which would silently
+        // assign boundary cells to the first pixel. Skip cells mapping
+        // outside the raster instead. (`arr.get` handles the upper bound.)
+        if px < 0.0 || py < 0.0 {
+            return None;
+        }
+        Some(Coord {
+            x: px as usize,
+            y: py as usize,
+        })
+    }
+
+    /// Find the array element located under the centroid of `cell`.
+    fn value_at_cell_centroid(&self, cell: CellIndex, lng_offset: f64) -> Option<&'a T> {
+        let coord = self.coord_at_cell_centroid(cell, lng_offset)?;
+        self.arr.get(match self.axis_order {
+            AxisOrder::XY => [coord.x, coord.y],
+            AxisOrder::YX => [coord.y, coord.x],
+        })
+    }
+
+    /// Like [`ArrayLookup::value_at_cell_centroid`], but also `None` for nodata.
+    fn data_at_cell_centroid(&self, cell: CellIndex, lng_offset: f64) -> Option<&'a T> {
+        self.value_at_cell_centroid(cell, lng_offset)
+            .filter(|value| self.nodata_value.as_ref() != Some(*value))
+    }
+
+    /// Classify the array elements located under `bbox`. Only the elements within
+    /// `array_window` are considered to be owned by the cell.
+    fn cell_block(
+        &self,
+        bbox: &Rect<f64>,
+        lng_offset: f64,
+        array_window: &Rect<usize>,
+    ) -> CellBlock<'a, T> {
+        let (mut px_min, mut px_max) = (f64::MAX, f64::MIN);
+        let (mut py_min, mut py_max) = (f64::MAX, f64::MIN);
+        for corner in bbox.to_polygon().exterior().coords() {
+            let transformed = point! {x: corner.x + lng_offset, y: corner.y}
+                .affine_transform(&self.inverse_transform);
+            px_min = px_min.min(transformed.x().floor());
+            px_max = px_max.max(transformed.x().floor());
+            py_min = py_min.min(transformed.y().floor());
+            py_max = py_max.max(transformed.y().floor());
+        }
+
+        // array_window is always located within the array
+        let (x_min, y_min) = (array_window.min().x as f64, array_window.min().y as f64);
+        let (x_max, y_max) = (array_window.max().x as f64, array_window.max().y as f64);
+        if px_max < x_min || py_max < y_min || px_min >= x_max || py_min >= y_max {
+            return CellBlock::Empty;
+        }
+        if px_min < x_min || py_min < y_min || px_max >= x_max || py_max >= y_max {
+            return CellBlock::Mixed;
+        }
+
+        let x_range = (px_min as usize)..=(px_max as usize);
+        let y_range = (py_min as usize)..=(py_max as usize);
+        let block = match self.axis_order {
+            AxisOrder::XY => self.arr.slice(s![x_range, y_range]),
+            AxisOrder::YX => self.arr.slice(s![y_range, x_range]),
+        };
+        let mut values = block.into_iter();
+        let Some(first) = values.next() else {
+            return CellBlock::Empty;
+        };
+        if !values.all(|value| value == first) {
+            CellBlock::Mixed
+        } else if self.nodata_value.as_ref() == Some(first) {
+            CellBlock::Empty
+        } else {
+            CellBlock::Homogeneous(first)
+        }
+    }
+}
+
+/// Convert the cells owned by `array_window`. `window` is the corresponding polygon
+/// in geographical coordinates.
+fn convert_array_window<'a, T>(
+    lookup: &ArrayLookup<'a, T>,
+    window: Polygon<f64>,
+    array_window: &Rect<usize>,
+    h3_resolution: Resolution,
+    compact: bool,
+) -> Result<HashMap<&'a T, CellCoverage>, Error>
+where
+    T: ArrayValue,
+{
+    let mut chunk_h3_map = HashMap::<&T, CellCoverage>::default();
+
+    for splitted_window in split_polygon_at_antimeridian(window) {
+        // h3 is only defined within -180 ... 180, so all windows after the antimeridian split
+        // should be in this range.
+        debug_assert!(
+            splitted_window
+                .polygon
+                .exterior()
+                .coords()
+                .all(|c| c.x >= -180.0 && c.x <= 180.0)
+        );
+
+        if compact {
+            convert_window_compacted(
+                lookup,
+                &splitted_window,
+                array_window,
+                h3_resolution,
+                &mut chunk_h3_map,
+            )?;
+            continue;
+        }
+
+        visit_window_cells(
+            lookup,
+            &splitted_window,
+            array_window,
+            h3_resolution,
+            |cell, value| {
+                chunk_h3_map
+                    .entry(value)
+                    .or_insert_with(CellCoverage::default)
+                    .insert(cell)
+            },
+        )?;
+    }
+
+    // Do an early compact to free a bit of memory. Each cell is owned by a single
+    // window, so there are no duplicates to remove when not compacting.
+    if compact {
+        #[cfg(feature = "rayon")]
+        let iter = chunk_h3_map.par_iter_mut();
+
+        #[cfg(not(feature = "rayon"))]
+        let mut iter = chunk_h3_map.iter_mut();
+
+        iter.try_for_each(|(_, cellset)| cellset.compact())?;
+    }
+
+    Ok(chunk_h3_map)
+}
+
+/// Call `visit` with all cells at `h3_resolution` owned by `array_window` and the array
+/// element under their centroid. Cells on nodata are skipped.
+///
+/// A cell is owned by the array window containing the element under its centroid, using
+/// half-open bounds. `splitted_window` is the window in geographical coordinates.
+fn visit_window_cells<'a, T, F>(
+    lookup: &ArrayLookup<'a, T>,
+    splitted_window: &SplittedPolygon,
+    array_window: &Rect<usize>,
+    h3_resolution: Resolution,
+    mut visit: F,
+) -> Result<(), Error>
+where
+    T: ArrayValue,
+    F: FnMut(CellIndex, &'a T),
+{
+    let mut tiler = TilerBuilder::new(h3_resolution)
+        .containment_mode(ContainmentMode::ContainsCentroid)
+        .build();
+    tiler.add(splitted_window.polygon.clone())?;
+    let lng_offset = splitted_window.difference_due_to_antimeridian_split;
+    for cell in tiler.into_coverage() {
+        if !owns_cell(lookup, array_window, cell, lng_offset) {
+            continue;
+        }
+        // find the array element for the coordinate of the h3 index
+        if let Some(value) = lookup.data_at_cell_centroid(cell, lng_offset) {
+            visit(cell, value);
+        }
+    }
+    Ok(())
+}
+
+/// Check if the element under the centroid of `cell` is located within `array_window`.
+fn owns_cell<T>(
+    lookup: &ArrayLookup<'_, T>,
+    array_window: &Rect<usize>,
+    cell: CellIndex,
+    lng_offset: f64,
+) -> bool
+where
+    T: ArrayValue,
+{
+    lookup
+        .coord_at_cell_centroid(cell, lng_offset)
+        .is_some_and(|coord| {
+            (array_window.min().x..array_window.max().x).contains(&coord.x)
+                && (array_window.min().y..array_window.max().y).contains(&coord.y)
+        })
+}
+
+/// Relative margin added to each side of the bounding box of a cell to also enclose all of
+/// its descendants. Descendants slightly extend beyond the boundary of their ancestor, by
+/// less than 8% of the extent of the ancestor.
+const DESCENDANTS_BBOX_MARGIN: f64 = 0.15;
+
+/// Bounding box enclosing the cell and all of its descendants.
+///
+/// Returns `None` for cells crossing the antimeridian, as their bounding box
+/// would span nearly the whole globe.
+fn descendants_bbox(cell: CellIndex) -> Option<Rect<f64>> {
+    let boundary = cell.boundary();
+    let mut ll_iter = boundary.iter();
+    let first = ll_iter.next()?;
+    let (mut min_lng, mut max_lng) = (first.lng(), first.lng());
+    let (mut min_lat, mut max_lat) = (first.lat(), first.lat());
+    for ll in ll_iter {
+        min_lng = min_lng.min(ll.lng());
+        max_lng = max_lng.max(ll.lng());
+        min_lat = min_lat.min(ll.lat());
+        max_lat = max_lat.max(ll.lat());
+    }
+    if max_lng - min_lng > 180.0 {
+        return None;
+    }
+    let margin_lng = (max_lng - min_lng) * DESCENDANTS_BBOX_MARGIN;
+    let margin_lat = (max_lat - min_lat) * DESCENDANTS_BBOX_MARGIN;
+    Some(Rect::new(
+        coord! {x: min_lng - margin_lng, y: min_lat - margin_lat},
+        coord! {x: max_lng + margin_lng, y: max_lat + margin_lat},
+    ))
+}
+
+/// The values of the array elements located below a cell and all of its descendants.
+enum CellBlock<'a, T> {
+    /// All elements share the same value.
+    Homogeneous(&'a T),
+
+    /// All elements are nodata, or the cell is located completely outside the array window.
+    Empty,
+
+    /// The elements differ, or the cell is partially located outside the array window.
+    Mixed,
+}
+
+/// Convert a window by descending the H3 hierarchy from coarse cells down to `h3_resolution`.
+///
+/// Coarse cells whose complete extent maps to a homogeneous block of array elements are
+/// emitted directly without enumerating their children, and blocks containing only nodata
+/// are skipped. The cells at `h3_resolution` are selected by their centroid, so the result
+/// is the same as compacting the cells generated by the tiler. Coarse cells are only emitted
+/// when all their descendants are owned by `array_window`.
+fn convert_window_compacted<'a, T>(
+    lookup: &ArrayLookup<'a, T>,
+    splitted_window: &SplittedPolygon,
+    array_window: &Rect<usize>,
+    h3_resolution: Resolution,
+    chunk_h3_map: &mut HashMap<&'a T, CellCoverage>,
+) -> Result<(), Error>
+where
+    T: ArrayValue,
+{
+    let window = &splitted_window.polygon;
+    let lng_offset = splitted_window.difference_due_to_antimeridian_split;
+
+    // start with cells roughly the size of the window
+    let window_area = window.area_on_sphere_m2();
+    let mut start_resolution = h3_resolution;
+    while let Some(coarser) = start_resolution.pred()
+        && coarser.area_m2() <= window_area
+    {
+        start_resolution = coarser;
+    }
+
+    let mut tiler = TilerBuilder::new(start_resolution)
+        .containment_mode(ContainmentMode::Covers)
+        .build();
+    tiler.add(window.clone())?;
+    // descendants of the direct neighbours of the covering cells may still
+    // reach into the window.
+    let mut cells = tiler
+        .into_coverage()
+        .flat_map(|cell| cell.grid_disk::<Vec<_>>(1))
+        .collect::<Vec<_>>();
+    cells.sort_unstable();
+    cells.dedup();
+
+    while let Some(cell) = cells.pop() {
+        let value = if cell.resolution() == h3_resolution {
+            let centroid: Coord = LatLng::from(cell).into();
+            if !window.intersects(&centroid) || !owns_cell(lookup, array_window, cell, lng_offset) {
+                continue;
+            }
+            match lookup.data_at_cell_centroid(cell, lng_offset) {
+                Some(value) => value,
+                None => continue,
+            }
+        } else {
+            let block = match descendants_bbox(cell) {
+                Some(bbox) if !window.intersects(&bbox) => continue,
+                Some(bbox) => lookup.cell_block(&bbox, lng_offset, array_window),
+                None => CellBlock::Mixed,
+            };
+            match block {
+                CellBlock::Homogeneous(value) => value,
+                CellBlock::Empty => continue,
+                CellBlock::Mixed => {
+                    if let Some(child_resolution) = cell.resolution().succ() {
+                        cells.extend(cell.children(child_resolution));
+                    }
+                    continue;
+                }
+            }
+        };
+        chunk_h3_map.entry(value).or_default().insert(cell);
+    }
+    Ok(())
+}
 
 fn finalize_chunk_map<T>(
     chunk_map: &mut HashMap<&T, CellCoverage>,
@@ -415,13 +1412,26 @@ where
 
 #[cfg(test)]
 mod tests {
-    use ndarray::array;
+    use std::collections::{HashMap, HashSet};
 
-    use crate::array::find_boxes_containing_data;
-    use crate::{AxisOrder, H3Converter, ResolutionSearchMode};
+    use geo::{AffineOps, BoundingRect};
+    use geo_types::Rect;
+    use h3o::geom::{ContainmentMode, TilerBuilder};
+    use h3o::{CellIndex, LatLng, Resolution};
+    use ndarray::{Array2, array};
+
+    use crate::array::LatitudeOverflow;
+    use crate::array::{
+        SparseDetection, find_rects_between_empty_lines, find_rects_containing_data,
+    };
+    use crate::util::split_polygon_at_antimeridian;
+    use crate::{
+        AxisOrder, CellValueStorage, Error, H3Converter, PixelRegistration, ResolutionSearchMode,
+        Sampling,
+    };
 
     #[test]
-    fn test_find_boxes_containing_data() {
+    fn test_find_rects_containing_data() {
         let arr = array![
             [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
             [0, 1, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0],
@@ -438,12 +1448,13 @@ mod tests {
         let n_elements = arr_copy.shape()[0] * arr_copy.shape()[1];
         let mut n_elements_in_boxes = 0;
 
-        for rect in find_boxes_containing_data(&arr.view(), &0, &AxisOrder::YX) {
-            n_elements_in_boxes +=
-                (rect.max().x - rect.min().x + 1) * (rect.max().y - rect.min().y + 1);
+        for rect in find_rects_containing_data(&arr.view(), &0, &AxisOrder::YX, 2, 4) {
+            n_elements_in_boxes += (rect.max().x - rect.min().x) * (rect.max().y - rect.min().y);
+            assert!(rect.max().x - rect.min().x <= 4);
+            assert!(rect.max().y - rect.min().y <= 4);
 
-            for x in rect.min().x..=rect.max().x {
-                for y in rect.min().y..=rect.max().y {
+            for x in rect.min().x..rect.max().x {
+                for y in rect.min().y..rect.max().y {
                     arr_copy[(y, x)] = 0;
                 }
             }
@@ -456,6 +1467,35 @@ mod tests {
         assert_eq!(arr_copy.sum(), 0);
     }
 
+    #[test]
+    fn test_find_rects_containing_data_diagonal() {
+        // no row or column is empty, so splitting at empty rows and columns would
+        // yield a single box covering the whole array
+        let arr = Array2::from_shape_fn((64, 64), |(y, x)| u8::from(y.abs_diff(x) < 2));
+
+        let between_empty_lines =
+            find_rects_between_empty_lines(&arr.view(), &0, &AxisOrder::YX, 64);
+        assert_eq!(between_empty_lines, vec![Rect::new((0, 0), (64, 64))]);
+
+        let rects = find_rects_containing_data(&arr.view(), &0, &AxisOrder::YX, 4, 16);
+        let n_elements_in_rects: usize = rects
+            .iter()
+            .map(|rect| (rect.max().x - rect.min().x) * (rect.max().y - rect.min().y))
+            .sum();
+        assert!(n_elements_in_rects < 64 * 64 / 4);
+
+        for y in 0..64 {
+            for x in 0..64 {
+                if arr[(y, x)] != 0 {
+                    assert!(rects.iter().any(|rect| {
+                        (rect.min().x..rect.max().x).contains(&x)
+                            && (rect.min().y..rect.max().y).contains(&y)
+                    }));
+                }
+            }
+        }
+    }
+
     #[test]
     fn preserve_nan_values() {
         use ordered_float::OrderedFloat;
@@ -512,4 +1552,558 @@ mod tests {
              max value seen = {max_value}"
         );
     }
+
+    #[test]
+    fn to_h3_adaptive_uses_local_resolution() {
+        // 1 degree pixels from the equator up to 88 degrees north. The pixels towards
+        // the pole are far smaller than the ones at the equator.
+        let arr = ndarray::Array2::<u8>::ones((88, 20));
+        let transform = crate::transform::from_gdal(&[0.0, 1.0, 0.0, 88.0, 0.0, -1.0]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u8), &transform, AxisOrder::YX);
+        let mut map = conv
+            .to_h3_adaptive(ResolutionSearchMode::MinDiff, false)
+            .unwrap();
+        assert_eq!(map.len(), 1);
+
+        let coverage = map.get_mut(&1_u8).unwrap();
+        let resolutions = coverage
+            .compacted_iter()
+            .map(|cell| cell.resolution())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert!(resolutions.len() > 1);
+
+        let finest = *resolutions.last().unwrap();
+        coverage.normalize(finest);
+        assert!(!coverage.is_empty());
+        assert!(
+            coverage
+                .compacted_iter()
+                .all(|cell| cell.resolution() == finest)
+        );
+    }
+
+    /// Compare the result of the direct compaction from homogeneous array blocks with
+    /// the compacted result of the conversion at the full resolution.
+    fn assert_compacted_matches_uncompacted(conv: &H3Converter<u16>, h3_resolution: Resolution) {
+        let compacted = conv.to_h3(h3_resolution, true).unwrap();
+        let mut uncompacted = conv.to_h3(h3_resolution, false).unwrap();
+        assert!(!compacted.is_empty());
+        assert_eq!(compacted.len(), uncompacted.len());
+
+        for (value, coverage) in compacted.iter() {
+            let expected_coverage = uncompacted.get_mut(value).unwrap();
+            expected_coverage.compact().unwrap();
+
+            let mut cells = coverage.compacted_iter().collect::<Vec<_>>();
+            let mut expected_cells = expected_coverage.compacted_iter().collect::<Vec<_>>();
+            cells.sort_unstable();
+            expected_cells.sort_unstable();
+            assert_eq!(cells, expected_cells, "coverage of value {value} differs");
+        }
+    }
+
+    fn classified_array() -> ndarray::Array2<u16> {
+        // large homogeneous blocks with a diagonal line of a different class and
+        // a nodata region
+        let mut arr = ndarray::Array2::<u16>::zeros((120, 150));
+        for ((r, c), v) in arr.indexed_iter_mut() {
+            *v = if r.abs_diff(c) < 2 {
+                9
+            } else if r < 20 && c < 30 {
+                0
+            } else {
+                (r / 40 * 4 + c / 50 + 1) as u16
+            };
+        }
+        arr
+    }
+
+    #[test]
+    fn rect_size_follows_resolution() {
+        let arr = classified_array();
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+
+        let rect_sizes = Resolution::range(Resolution::Two, Resolution::Fifteen)
+            .map(|res| conv.rect_size(res))
+            .collect::<Vec<_>>();
+        assert!(rect_sizes.windows(2).all(|w| w[0] >= w[1]));
+        assert_eq!(rect_sizes.first(), Some(&super::MAX_RECT_SIZE));
+        assert_eq!(rect_sizes.last(), Some(&super::MIN_RECT_SIZE));
+
+        // a rect at a resolution in between is expected to hold about the targeted number of cells
+        let rect_size = conv.rect_size(Resolution::Nine) as f64;
+        let pixel_area = 1_110.0 * 730.0;
+        let n_cells = rect_size * rect_size * pixel_area / Resolution::Nine.area_m2();
+        assert!((0.5..2.0).contains(&(n_cells / super::TARGET_CELLS_PER_RECT)));
+    }
+
+    #[test]
+    fn rects_own_disjoint_cells() {
+        let arr = classified_array();
+        let view = arr.view();
+        let transforms = [
+            crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]),
+            // rotated, crossing the antimeridian
+            crate::transform::from_gdal(&[179.0, 0.008, 0.003, 49.0, 0.002, -0.009]),
+        ];
+        for transform in transforms.iter() {
+            let conv = H3Converter::new(&view, &Some(0_u16), transform, AxisOrder::YX);
+            for compact in [false, true] {
+                let chunk_maps = conv
+                    .convert_rects_separately(16, |_| Ok(Resolution::Seven), compact)
+                    .unwrap();
+                assert!(chunk_maps.len() > 10);
+
+                let mut seen = HashSet::new();
+                for chunk_map in chunk_maps.iter() {
+                    for cell in chunk_map
+                        .values()
+                        .flat_map(|coverage| coverage.uncompacted_iter(Resolution::Seven))
+                    {
+                        assert!(seen.insert(cell), "{cell} is owned by multiple rects");
+                    }
+                }
+
+                // ... and no cell got lost
+                let expected = brute_force_to_h3(&arr, transform, Resolution::Seven)
+                    .into_values()
+                    .map(|cells| cells.len())
+                    .sum::<usize>();
+                assert_eq!(seen.len(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn sparse_detection_strategies_match() {
+        let arr = Array2::from_shape_fn((300, 300), |(y, x)| {
+            u16::from(y.abs_diff(x) < 5 || (y / 40 == 5 && x / 40 == 1))
+        });
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let mut conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        let expected = conv.to_h3(Resolution::Eight, false).unwrap();
+
+        conv.sparse_detection = SparseDetection::EmptyLines;
+        let converted = conv.to_h3(Resolution::Eight, false).unwrap();
+        assert_eq!(converted.len(), expected.len());
+        for (value, coverage) in expected.iter() {
+            assert_eq!(
+                converted[value].compacted_iter().collect::<Vec<_>>(),
+                coverage.compacted_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn conversion_with_configured_threads() {
+        let arr = classified_array();
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        let expected = conv.to_h3(Resolution::Seven, true).unwrap();
+
+        let assert_matches_expected = |conv: H3Converter<u16>| {
+            let converted = conv.to_h3(Resolution::Seven, true).unwrap();
+            assert_eq!(converted.len(), expected.len());
+            for (value, coverage) in converted.iter() {
+                assert_eq!(
+                    coverage.compacted_iter().collect::<Vec<_>>(),
+                    expected[value].compacted_iter().collect::<Vec<_>>()
+                );
+            }
+        };
+        assert_matches_expected(
+            H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX).with_num_threads(2),
+        );
+
+        #[cfg(feature = "rayon")]
+        {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(2)
+                .build()
+                .unwrap();
+            assert_matches_expected(
+                H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX)
+                    .with_thread_pool(std::sync::Arc::new(pool)),
+            );
+        }
+    }
+
+    #[test]
+    fn to_h3_within_memory_budget() {
+        let arr = classified_array();
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+
+        let spill_directory = std::env::temp_dir().join(format!(
+            "rasterh3-test-memory-budget-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&spill_directory).unwrap();
+        for compact in [false, true] {
+            let expected = conv.to_h3(Resolution::Seven, compact).unwrap();
+
+            // a budget of a few cells forces spilling after every rect
+            let converted = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX)
+                .with_memory_budget(64)
+                .with_spill_directory(&spill_directory)
+                .to_h3(Resolution::Seven, compact)
+                .unwrap();
+            assert_eq!(converted.len(), expected.len());
+            for (value, coverage) in converted.iter() {
+                assert_eq!(
+                    coverage.compacted_iter().collect::<Vec<_>>(),
+                    expected[value].compacted_iter().collect::<Vec<_>>()
+                );
+            }
+
+            // the spilled runs got removed
+            assert_eq!(std::fs::read_dir(&spill_directory).unwrap().count(), 0);
+        }
+        std::fs::remove_dir(&spill_directory).unwrap();
+    }
+
+    #[test]
+    fn to_h3_compacted_from_homogeneous_blocks() {
+        let arr = classified_array();
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        assert_compacted_matches_uncompacted(&conv, Resolution::Eight);
+
+        let conv = H3Converter::new(&view, &None, &transform, AxisOrder::YX);
+        assert_compacted_matches_uncompacted(&conv, Resolution::Eight);
+    }
+
+    #[test]
+    fn to_h3_compacted_from_homogeneous_blocks_antimeridian() {
+        let arr = classified_array();
+        let transform = crate::transform::from_gdal(&[179.0, 0.01, 0.0, -20.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        assert_compacted_matches_uncompacted(&conv, Resolution::Seven);
+    }
+
+    #[test]
+    fn pixel_registration() {
+        let arr = classified_array();
+        let view = arr.view();
+        let area_transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        // the same raster, georeferenced by the center of the upper left pixel
+        let point_transform = crate::transform::from_gdal(&[8.005, 0.01, 0.0, 48.995, 0.0, -0.01]);
+
+        let area = H3Converter::new(&view, &Some(0_u16), &area_transform, AxisOrder::YX)
+            .to_h3(Resolution::Eight, false)
+            .unwrap();
+
+        let point = H3Converter::new(&view, &Some(0_u16), &point_transform, AxisOrder::YX)
+            .with_pixel_registration(PixelRegistration::Point);
+        assert_eq!(
+            point
+                .nearest_h3_resolution(ResolutionSearchMode::MinDiff)
+                .unwrap(),
+            H3Converter::new(&view, &Some(0_u16), &area_transform, AxisOrder::YX)
+                .nearest_h3_resolution(ResolutionSearchMode::MinDiff)
+                .unwrap()
+        );
+        let point = point.to_h3(Resolution::Eight, false).unwrap();
+        assert_eq!(area.len(), point.len());
+        for (value, coverage) in area.iter() {
+            assert_eq!(
+                coverage.compacted_iter().collect::<HashSet<_>>(),
+                point[value].compacted_iter().collect::<HashSet<_>>()
+            );
+        }
+
+        // treating the point-registered raster as area-registered shifts it by half a pixel
+        let shifted = H3Converter::new(&view, &Some(0_u16), &point_transform, AxisOrder::YX)
+            .to_h3(Resolution::Eight, false)
+            .unwrap();
+        assert!(area.iter().any(|(value, coverage)| {
+            coverage.compacted_iter().collect::<HashSet<_>>()
+                != shifted[value].compacted_iter().collect::<HashSet<_>>()
+        }));
+    }
+
+    /// Convert by looking up the centroid of every cell within the bounding box of the raster.
+    fn brute_force_to_h3(
+        arr: &ndarray::Array2<u16>,
+        transform: &geo::AffineTransform<f64>,
+        h3_resolution: Resolution,
+    ) -> HashMap<u16, HashSet<CellIndex>> {
+        let view = arr.view();
+        let lookup = H3Converter::new(&view, &Some(0_u16), transform, AxisOrder::YX)
+            .lookup()
+            .unwrap();
+        let footprint = Rect::new((0.0, 0.0), (arr.shape()[1] as f64, arr.shape()[0] as f64))
+            .to_polygon()
+            .affine_transform(transform);
+
+        let mut expected = HashMap::<u16, HashSet<CellIndex>>::new();
+        for splitted in
+            split_polygon_at_antimeridian(footprint.bounding_rect().unwrap().to_polygon())
+        {
+            let mut tiler = TilerBuilder::new(h3_resolution)
+                .containment_mode(ContainmentMode::ContainsCentroid)
+                .build();
+            tiler.add(splitted.polygon).unwrap();
+            for cell in tiler.into_coverage() {
+                if let Some(value) = lookup
+                    .data_at_cell_centroid(cell, splitted.difference_due_to_antimeridian_split)
+                {
+                    expected.entry(*value).or_default().insert(cell);
+                }
+            }
+        }
+        expected
+    }
+
+    #[test]
+    fn rotated_and_sheared_transforms() {
+        let arr = classified_array();
+        let view = arr.view();
+        let (sin, cos) = 20.0_f64.to_radians().sin_cos();
+        let transforms = [
+            // rotated by 20 degrees
+            [8.0, 0.01 * cos, 0.01 * sin, 49.0, 0.01 * sin, -0.01 * cos],
+            // sheared
+            [8.0, 0.01, 0.004, 49.0, -0.003, -0.01],
+            // rotated and crossing the antimeridian
+            [
+                179.0,
+                0.01 * cos,
+                -0.01 * sin,
+                -20.0,
+                -0.01 * sin,
+                -0.01 * cos,
+            ],
+        ];
+
+        for gt in transforms {
+            let transform = crate::transform::from_gdal(&gt);
+            let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+            let expected = brute_force_to_h3(&arr, &transform, Resolution::Seven);
+            let converted = conv.to_h3(Resolution::Seven, false).unwrap();
+            assert_eq!(converted.len(), expected.len());
+            for (value, coverage) in converted.iter() {
+                assert_eq!(
+                    coverage.compacted_iter().collect::<HashSet<_>>(),
+                    expected[*value],
+                    "coverage of value {value} differs for {gt:?}"
+                );
+            }
+            assert_compacted_matches_uncompacted(&conv, Resolution::Seven);
+        }
+    }
+
+    /// Convert by looking up the centroid of every cell of the globe.
+    fn global_brute_force_to_h3(
+        arr: &ndarray::Array2<u16>,
+        transform: &geo::AffineTransform<f64>,
+        h3_resolution: Resolution,
+    ) -> HashMap<u16, HashSet<CellIndex>> {
+        let view = arr.view();
+        let lookup = H3Converter::new(&view, &Some(0_u16), transform, AxisOrder::YX)
+            .lookup()
+            .unwrap();
+        let mut expected = HashMap::<u16, HashSet<CellIndex>>::new();
+        for cell in CellIndex::base_cells().flat_map(|cell| cell.children(h3_resolution)) {
+            for lng_offset in [0.0, 360.0, -360.0] {
+                if let Some(value) = lookup.value_at_cell_centroid(cell, lng_offset) {
+                    if *value != 0 {
+                        expected.entry(*value).or_default().insert(cell);
+                    }
+                    break;
+                }
+            }
+        }
+        expected
+    }
+
+    fn assert_matches_global_brute_force(
+        arr: &ndarray::Array2<u16>,
+        transform: &geo::AffineTransform<f64>,
+        h3_resolution: Resolution,
+    ) {
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u16), transform, AxisOrder::YX);
+        let expected = global_brute_force_to_h3(arr, transform, h3_resolution);
+        assert!(!expected.is_empty());
+        let converted = conv.to_h3(h3_resolution, false).unwrap();
+        assert_eq!(converted.len(), expected.len());
+        for (value, coverage) in converted.iter() {
+            let cells = coverage.compacted_iter().collect::<HashSet<_>>();
+            assert_eq!(
+                cells.len(),
+                expected[*value].len(),
+                "number of cells of value {value} differs"
+            );
+            assert_eq!(cells, expected[*value], "coverage of value {value} differs");
+        }
+        assert_compacted_matches_uncompacted(&conv, h3_resolution);
+    }
+
+    #[test]
+    fn polar_grids() {
+        let arr =
+            ndarray::Array2::from_shape_fn((40, 720), |(r, c)| (r / 10 * 4 + c / 180 + 1) as u16);
+        // arctic, latitudes 90 to 70
+        assert_matches_global_brute_force(
+            &arr,
+            &crate::transform::from_gdal(&[-180.0, 0.5, 0.0, 90.0, 0.0, -0.5]),
+            Resolution::Three,
+        );
+        // antarctic, latitudes -70 to -90
+        assert_matches_global_brute_force(
+            &arr,
+            &crate::transform::from_gdal(&[-180.0, 0.5, 0.0, -70.0, 0.0, -0.5]),
+            Resolution::Three,
+        );
+    }
+
+    #[test]
+    fn polar_grids_beyond_the_poles() {
+        let arr =
+            ndarray::Array2::from_shape_fn((40, 720), |(r, c)| (r / 10 * 4 + c / 180 + 1) as u16);
+        let view = arr.view();
+        // the first 10 rows are located beyond the north pole
+        let transform = crate::transform::from_gdal(&[-180.0, 0.5, 0.0, 95.0, 0.0, -0.5]);
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        assert!(matches!(
+            conv.to_h3(Resolution::Three, false),
+            Err(Error::LatitudeOutOfRange(_))
+        ));
+        // the resolution can be determined from the rows within the valid latitudes
+        let range = conv
+            .h3_resolution_range(ResolutionSearchMode::MinDiff, 4)
+            .unwrap();
+        assert!(
+            conv.nearest_h3_resolution(ResolutionSearchMode::MinDiff)
+                .is_ok()
+        );
+
+        let conv = conv.with_latitude_overflow(LatitudeOverflow::Clamp);
+        assert_eq!(
+            conv.h3_resolution_range(ResolutionSearchMode::MinDiff, 4)
+                .unwrap(),
+            range
+        );
+        assert!(
+            conv.to_h3_adaptive(ResolutionSearchMode::MinDiff, false)
+                .is_ok()
+        );
+        assert_eq!(
+            conv.to_h3(Resolution::Three, false).unwrap().len(),
+            // values of the rows beyond the pole are missing
+            arr.iter().collect::<HashSet<_>>().len() - 4
+        );
+
+        // the same applies to the south pole
+        let transform = crate::transform::from_gdal(&[-180.0, 0.5, 0.0, -75.0, 0.0, -0.5]);
+        let conv = H3Converter::new(&view, &Some(0_u16), &transform, AxisOrder::YX);
+        assert!(matches!(
+            conv.to_cell_value_map(Resolution::Three, CellValueStorage::Sorted),
+            Err(Error::LatitudeOutOfRange(_))
+        ));
+        let conv = conv.with_latitude_overflow(LatitudeOverflow::Clamp);
+        assert_eq!(
+            conv.to_cell_value_map(Resolution::Three, CellValueStorage::Sorted)
+                .unwrap()
+                .len(),
+            conv.to_h3(Resolution::Three, false)
+                .unwrap()
+                .values()
+                .map(|coverage| coverage.len())
+                .sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn global_raster_with_longitude_wrap() {
+        // 1 degree pixels, with distinct values in the first and the last column
+        let arr = ndarray::Array2::from_shape_fn((180, 360), |(r, c)| match c {
+            0 => 100,
+            359 => 200,
+            _ => (c / 30 + r / 45 * 12 + 1) as u16,
+        });
+        let view = arr.view();
+        let n_cells = CellIndex::base_cells()
+            .map(|cell| cell.children_count(Resolution::Two))
+            .sum::<u64>() as usize;
+
+        for west in [0.0, -180.0] {
+            let transform = crate::transform::from_gdal(&[west, 1.0, 0.0, 90.0, 0.0, -1.0]);
+            let conv =
+                H3Converter::new(&view, &None, &transform, AxisOrder::YX).with_longitude_wrap(true);
+
+            let mut expected = HashMap::<u16, HashSet<CellIndex>>::new();
+            for cell in CellIndex::base_cells().flat_map(|cell| cell.children(Resolution::Two)) {
+                let ll = LatLng::from(cell);
+                let column = (ll.lng() - west).rem_euclid(360.0).floor() as usize;
+                let row = (90.0 - ll.lat()).floor() as usize;
+                expected.entry(arr[[row, column]]).or_default().insert(cell);
+            }
+            // the cells at the seam get the values of the correct edge column
+            assert!(!expected[&100].is_empty());
+            assert!(!expected[&200].is_empty());
+
+            let converted = conv.to_h3(Resolution::Two, false).unwrap();
+            assert_eq!(
+                converted.values().map(|c| c.len()).sum::<usize>(),
+                n_cells,
+                "every cell of the globe is converted exactly once"
+            );
+            assert_eq!(converted.len(), expected.len());
+            for (value, coverage) in converted.iter() {
+                assert_eq!(
+                    coverage.compacted_iter().collect::<HashSet<_>>(),
+                    expected[*value],
+                    "coverage of value {value} differs"
+                );
+            }
+            assert_compacted_matches_uncompacted(&conv, Resolution::Two);
+        }
+    }
+
+    #[test]
+    fn global_raster_sampling_across_the_seam() {
+        let arr = ndarray::Array2::from_shape_fn((180, 360), |(_, c)| c as u16);
+        let view = arr.view();
+        let transform = crate::transform::from_gdal(&[0.0, 1.0, 0.0, 90.0, 0.0, -1.0]);
+        let cell = LatLng::new(10.2, 0.0).unwrap().to_cell(Resolution::Ten);
+
+        let sample = |conv: H3Converter<u16>| {
+            *conv
+                .sample_cells_numeric([cell], Sampling::Bilinear, CellValueStorage::Sorted)
+                .unwrap()
+                .get(cell)
+                .unwrap()
+        };
+        // the pixel centers next to the seam are at 359.5 and 0.5 degrees
+        let wrapped = sample(
+            H3Converter::new(&view, &None, &transform, AxisOrder::YX).with_longitude_wrap(true),
+        );
+        assert!((170.0..190.0).contains(&wrapped));
+        let not_wrapped = sample(H3Converter::new(&view, &None, &transform, AxisOrder::YX));
+        assert!(!(1.0..=358.0).contains(&not_wrapped));
+    }
+
+    #[test]
+    fn longitude_wrap_requires_global_raster() {
+        let arr = ndarray::Array2::<u16>::ones((180, 359));
+        let view = arr.view();
+        let transform = crate::transform::from_gdal(&[0.0, 1.0, 0.0, 90.0, 0.0, -1.0]);
+        let conv =
+            H3Converter::new(&view, &None, &transform, AxisOrder::YX).with_longitude_wrap(true);
+        assert!(matches!(
+            conv.to_h3(Resolution::Two, false),
+            Err(Error::NotGlobal)
+        ));
+    }
 }
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use h3o::{CellIndex, Resolution};
+
+use crate::{CellCoverage, Error};
+
+/// The storage used by a [`CellValueMap`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CellValueStorage {
+    /// A vec sorted by the cells. Compact in memory, lookups use a binary search.
+    #[default]
+    Sorted,
+
+    /// A hashmap. Faster lookups at the cost of a higher memory usage.
+    Hash,
+}
+
+#[derive(Clone, Debug)]
+enum Cells<T> {
+    Sorted(Vec<(CellIndex, T)>),
+    Hash(ahash::HashMap<CellIndex, T>),
+}
+
+/// A map of cells to the value of the raster at their location.
+///
+/// This is the inverted orientation of the value -> [`CellCoverage`] maps returned by
+/// [`H3Converter::to_h3`](crate::H3Converter::to_h3), suited for joins on the cells.
+#[derive(Clone, Debug)]
+pub struct CellValueMap<T> {
+    cells: Cells<T>,
+}
+
+impl<T> CellValueMap<T> {
+    /// Build from pairs of cells and values. When a cell is contained multiple times,
+    /// the first occurrence is kept.
+    pub fn from_cells(
+        cells: impl IntoIterator<Item = (CellIndex, T)>,
+        storage: CellValueStorage,
+    ) -> Self {
+        let cells = match storage {
+            CellValueStorage::Sorted => {
+                let mut cells = cells.into_iter().collect::<Vec<_>>();
+                cells.sort_by_key(|(cell, _)| *cell);
+                cells.dedup_by_key(|(cell, _)| *cell);
+                cells.shrink_to_fit();
+                Cells::Sorted(cells)
+            }
+            CellValueStorage::Hash => {
+                let mut map = ahash::HashMap::default();
+                for (cell, value) in cells {
+                    map.entry(cell).or_insert(value);
+                }
+                Cells::Hash(map)
+            }
+        };
+        Self { cells }
+    }
+
+    /// Build from a value -> [`CellCoverage`] map.
+    ///
+    /// The coverages get uncompacted to `resolution`, cells of finer resolutions are ignored.
+    pub fn from_grouped<S>(
+        map: &HashMap<T, CellCoverage, S>,
+        resolution: Resolution,
+        storage: CellValueStorage,
+    ) -> Self
+    where
+        T: Clone,
+    {
+        Self::from_cells(
+            map.iter().flat_map(|(value, coverage)| {
+                coverage
+                    .uncompacted_iter(resolution)
+                    .map(move |cell| (cell, value.clone()))
+            }),
+            storage,
+        )
+    }
+
+    /// Group the cells by their values.
+    ///
+    /// With `compact` the resulting coverages get compacted.
+    pub fn into_grouped(self, compact: bool) -> Result<ahash::HashMap<T, CellCoverage>, Error>
+    where
+        T: Eq + Hash,
+    {
+        let mut grouped = ahash::HashMap::<T, CellCoverage>::default();
+        for (cell, value) in self.into_iter() {
+            grouped.entry(value).or_default().insert(cell);
+        }
+        for coverage in grouped.values_mut() {
+            coverage.finalize(compact)?;
+        }
+        Ok(grouped)
+    }
+
+    pub fn storage(&self) -> CellValueStorage {
+        match self.cells {
+            Cells::Sorted(_) => CellValueStorage::Sorted,
+            Cells::Hash(_) => CellValueStorage::Hash,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.cells {
+            Cells::Sorted(cells) => cells.len(),
+            Cells::Hash(cells) => cells.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the value of the given cell.
+    pub fn get(&self, cell: CellIndex) -> Option<&T> {
+        match &self.cells {
+            Cells::Sorted(cells) => cells
+                .binary_search_by_key(&cell, |(c, _)| *c)
+                .ok()
+                .map(|pos| &cells[pos].1),
+            Cells::Hash(cells) => cells.get(&cell),
+        }
+    }
+
+    pub fn contains(&self, cell: CellIndex) -> bool {
+        self.get(cell).is_some()
+    }
+
+    /// Iterate over all cells and their values. With [`CellValueStorage::Sorted`]
+    /// the cells are returned in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (CellIndex, &T)> + '_ {
+        let (sorted, hashed) = match &self.cells {
+            Cells::Sorted(cells) => (Some(cells.iter().map(|(c, v)| (*c, v))), None),
+            Cells::Hash(cells) => (None, Some(cells.iter().map(|(c, v)| (*c, v)))),
+        };
+        sorted
+            .into_iter()
+            .flatten()
+            .chain(hashed.into_iter().flatten())
+    }
+
+    /// Iterate over all contained cells located within `parent`, including `parent` itself.
+    ///
+    /// With [`CellValueStorage::Sorted`] this is a range query, with [`CellValueStorage::Hash`]
+    /// all cells are visited.
+    pub fn iter_within(&self, parent: CellIndex) -> impl Iterator<Item = (CellIndex, &T)> + '_ {
+        let (sorted, hashed) = match &self.cells {
+            Cells::Sorted(cells) => {
+                // cells are ordered hierarchically, so all descendants of the parent are
+                // located in one range ending with the parent itself.
+                let first = parent.center_child(Resolution::Fifteen).unwrap_or(parent);
+                let start = cells.partition_point(|(c, _)| *c < first);
+                let end = cells.partition_point(|(c, _)| *c <= parent);
+                (Some(cells[start..end].iter().map(|(c, v)| (*c, v))), None)
+            }
+            Cells::Hash(cells) => {
+                let within = cells.iter().filter_map(move |(c, v)| {
+                    (c.resolution() >= parent.resolution()
+                        && c.parent(parent.resolution()) == Some(parent))
+                    .then_some((*c, v))
+                });
+                (None, Some(within))
+            }
+        };
+        sorted
+            .into_iter()
+            .flatten()
+            .chain(hashed.into_iter().flatten())
+    }
+}
+
+impl<T> IntoIterator for CellValueMap<T> {
+    type Item = (CellIndex, T);
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        match self.cells {
+            Cells::Sorted(cells) => IntoIter(IntoIterInner::Sorted(cells.into_iter())),
+            Cells::Hash(cells) => IntoIter(IntoIterInner::Hash(cells.into_iter())),
+        }
+    }
+}
+
+/// Owning iterator over the cells and values of a [`CellValueMap`].
+pub struct IntoIter<T>(IntoIterInner<T>);
+
+enum IntoIterInner<T> {
+    Sorted(std::vec::IntoIter<(CellIndex, T)>),
+    Hash(std::collections::hash_map::IntoIter<CellIndex, T>),
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = (CellIndex, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            IntoIterInner::Sorted(iter) => iter.next(),
+            IntoIterInner::Hash(iter) => iter.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use h3o::{LatLng, Resolution};
+
+    use crate::cell_value_map::{CellValueMap, CellValueStorage};
+    use crate::{AxisOrder, H3Converter};
+
+    #[test]
+    fn test_iter_within() {
+        let parent = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Six);
+        let outside = LatLng::new(-20.0, 100.0)
+            .unwrap()
+            .to_cell(Resolution::Eight);
+        let cells =
+            parent
+                .children(Resolution::Eight)
+                .chain(parent.children(Resolution::Seven))
+                .chain(parent.grid_ring_fast(1).flatten().flat_map(|neighbour| {
+                    neighbour.children(Resolution::Eight).collect::<Vec<_>>()
+                }))
+                .chain([parent, outside])
+                .map(|cell| (cell, u8::from(cell.resolution())))
+                .collect::<Vec<_>>();
+
+        for storage in [CellValueStorage::Sorted, CellValueStorage::Hash] {
+            let map = CellValueMap::from_cells(cells.iter().copied(), storage);
+            assert_eq!(map.storage(), storage);
+            assert_eq!(map.get(outside), Some(&8));
+
+            let mut within = map.iter_within(parent).collect::<Vec<_>>();
+            within.sort_unstable();
+            assert_eq!(
+                within.len() as u64,
+                1 + parent.children_count(Resolution::Seven)
+                    + parent.children_count(Resolution::Eight)
+            );
+            assert!(
+                within
+                    .iter()
+                    .all(|(cell, _)| cell.parent(Resolution::Six) == Some(parent))
+            );
+
+            let into_iter: crate::IntoIter<u8> = map.into_iter();
+            assert_eq!(into_iter.count(), cells.len());
+        }
+    }
+
+    #[test]
+    fn test_to_cell_value_map() {
+        let arr = ndarray::Array2::from_shape_fn((40, 50), |(r, c)| ((r / 10) * 5 + c / 10) as u8);
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &Some(0_u8), &transform, AxisOrder::YX);
+        let grouped = conv.to_h3(Resolution::Eight, false).unwrap();
+
+        for storage in [CellValueStorage::Sorted, CellValueStorage::Hash] {
+            let cell_values = conv.to_cell_value_map(Resolution::Eight, storage).unwrap();
+            assert_eq!(
+                cell_values.len(),
+                grouped
+                    .values()
+                    .map(|coverage| coverage.len())
+                    .sum::<usize>()
+            );
+            for (value, coverage) in grouped.iter() {
+                assert!(
+                    coverage
+                        .compacted_iter()
+                        .all(|cell| cell_values.get(cell) == Some(value))
+                );
+            }
+
+            let from_grouped = CellValueMap::from_grouped(&grouped, Resolution::Eight, storage);
+            assert_eq!(from_grouped.len(), cell_values.len());
+
+            let regrouped = cell_values.into_grouped(false).unwrap();
+            assert_eq!(regrouped.len(), grouped.len());
+            for (value, coverage) in regrouped.iter() {
+                assert_eq!(coverage.len(), grouped[value].len());
+            }
+        }
+    }
+}
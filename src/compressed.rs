@@ -0,0 +1,285 @@
+use h3o::{CellIndex, Resolution};
+
+use crate::CellCoverage;
+
+/// Number of cells per block. Membership checks decode at most one block.
+const CELLS_PER_BLOCK: usize = 128;
+
+/// A read-only [`CellCoverage`] stored in a compressed form.
+///
+/// The cells of each resolution are sorted and split into blocks. Within a block only the
+/// difference to the previous cell is stored as a variable-length integer. As the unused
+/// trailing digits of a cell index are left out, neighbouring cells mostly differ by a
+/// single byte, so this typically takes a fraction of the 8 bytes per cell used by
+/// [`CellCoverage`].
+///
+/// Iteration yields the cells in the same order as a deduplicated [`CellCoverage`].
+#[derive(Clone, Debug, Default)]
+pub struct CompressedCellCoverage {
+    cells_by_resolution: [CompressedCells; 16],
+}
+
+impl CompressedCellCoverage {
+    pub fn len(&self) -> usize {
+        self.cells_by_resolution.iter().map(|cells| cells.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells_by_resolution.iter().all(|cells| cells.len == 0)
+    }
+
+    /// Check if `cell` itself is contained.
+    pub fn contains(&self, cell: CellIndex) -> bool {
+        self.cells_by_resolution[usize::from(cell.resolution())].contains(cell)
+    }
+
+    /// Check if the coverage covers `cell`, either by containing the cell itself or
+    /// one of its ancestors.
+    pub fn covers(&self, cell: CellIndex) -> bool {
+        Resolution::range(Resolution::Zero, cell.resolution())
+            .filter_map(|res| cell.parent(res))
+            .any(|ancestor| self.contains(ancestor))
+    }
+
+    pub fn compacted_iter(&self) -> impl Iterator<Item = CellIndex> + '_ {
+        self.cells_by_resolution
+            .iter()
+            .enumerate()
+            .flat_map(|(r_idx, cells)| cells.iter(r_idx))
+    }
+
+    pub fn uncompacted_iter(&self, r: Resolution) -> impl Iterator<Item = CellIndex> + '_ {
+        let r_idx: usize = r.into();
+        (0..=r_idx).flat_map(move |r_idx| {
+            self.cells_by_resolution[r_idx]
+                .iter(r_idx)
+                .flat_map(move |cell| cell.children(r))
+        })
+    }
+
+    /// Number of bytes allocated for the compressed cells.
+    pub fn memory_size(&self) -> usize {
+        self.cells_by_resolution
+            .iter()
+            .map(|cells| {
+                cells.data.capacity()
+                    + cells.block_starts.capacity() * size_of::<u64>()
+                    + cells.block_offsets.capacity() * size_of::<usize>()
+            })
+            .sum()
+    }
+}
+
+impl From<&CellCoverage> for CompressedCellCoverage {
+    fn from(coverage: &CellCoverage) -> Self {
+        let mut compressed = Self::default();
+        for (r_idx, cells) in coverage.cells_by_resolution.iter().enumerate() {
+            let mut cells = cells.clone();
+            cells.sort_unstable();
+            cells.dedup();
+            compressed.cells_by_resolution[r_idx] = CompressedCells::new(&cells, r_idx);
+        }
+        compressed
+    }
+}
+
+impl From<&CompressedCellCoverage> for CellCoverage {
+    fn from(compressed: &CompressedCellCoverage) -> Self {
+        let mut coverage = CellCoverage::default();
+        for cell in compressed.compacted_iter() {
+            coverage.insert(cell);
+        }
+        coverage
+    }
+}
+
+/// The sorted cells of a single resolution.
+#[derive(Clone, Debug, Default)]
+struct CompressedCells {
+    len: usize,
+
+    /// the first packed cell of each block
+    block_starts: Vec<u64>,
+
+    /// the offset of the encoded differences of each block in `data`
+    block_offsets: Vec<usize>,
+
+    /// the differences between the packed cells of the blocks as LEB128 varints
+    data: Vec<u8>,
+}
+
+impl CompressedCells {
+    fn new(sorted_cells: &[CellIndex], r_idx: usize) -> Self {
+        let mut compressed = Self {
+            len: sorted_cells.len(),
+            ..Default::default()
+        };
+        for block in sorted_cells.chunks(CELLS_PER_BLOCK) {
+            let mut previous = pack(block[0], r_idx);
+            compressed.block_starts.push(previous);
+            compressed.block_offsets.push(compressed.data.len());
+            for cell in &block[1..] {
+                let packed = pack(*cell, r_idx);
+                write_varint(&mut compressed.data, packed - previous);
+                previous = packed;
+            }
+        }
+        compressed.data.shrink_to_fit();
+        compressed
+    }
+
+    fn contains(&self, cell: CellIndex) -> bool {
+        let r_idx = usize::from(cell.resolution());
+        let packed = pack(cell, r_idx);
+        let block_i = match self.block_starts.binary_search(&packed) {
+            Ok(_) => return true,
+            Err(0) => return false,
+            Err(insert_i) => insert_i - 1,
+        };
+        self.block(block_i, r_idx)
+            .map(|cell| pack(cell, r_idx))
+            .take_while(|block_packed| *block_packed <= packed)
+            .any(|block_packed| block_packed == packed)
+    }
+
+    fn iter(&self, r_idx: usize) -> impl Iterator<Item = CellIndex> + '_ {
+        (0..self.block_starts.len()).flat_map(move |block_i| self.block(block_i, r_idx))
+    }
+
+    /// Decode the cells of a block.
+    fn block(&self, block_i: usize, r_idx: usize) -> impl Iterator<Item = CellIndex> + '_ {
+        let block_len = CELLS_PER_BLOCK.min(self.len - block_i * CELLS_PER_BLOCK);
+        let mut offset = self.block_offsets[block_i];
+        let mut packed = self.block_starts[block_i];
+        (0..block_len).map(move |cell_i| {
+            if cell_i > 0 {
+                packed += read_varint(&self.data, &mut offset);
+            }
+            unpack(packed, r_idx)
+        })
+    }
+}
+
+/// Number of trailing bits of the cells of a resolution occupied by unused digits.
+const fn unused_bits(r_idx: usize) -> u32 {
+    3 * (15 - r_idx as u32)
+}
+
+/// Leave out the unused digits, which are always set to 7.
+fn pack(cell: CellIndex, r_idx: usize) -> u64 {
+    u64::from(cell) >> unused_bits(r_idx)
+}
+
+fn unpack(packed: u64, r_idx: usize) -> CellIndex {
+    let unused_bits = unused_bits(r_idx);
+    let unused_digits = (1u64 << unused_bits) - 1;
+    CellIndex::try_from((packed << unused_bits) | unused_digits)
+        .expect("cell index got corrupted in compressed storage")
+}
+
+fn write_varint(data: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        data.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_varint(data: &[u8], offset: &mut usize) -> u64 {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = data[*offset];
+        *offset += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use h3o::{LatLng, Resolution};
+
+    use crate::{CellCoverage, CompressedCellCoverage};
+
+    fn coverage() -> CellCoverage {
+        let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Ten);
+        let mut coverage = CellCoverage::default();
+        for cell in center.grid_disk::<Vec<_>>(30) {
+            coverage.insert(cell);
+        }
+        coverage.compact().unwrap();
+        coverage
+    }
+
+    #[test]
+    fn same_cells_as_uncompressed() {
+        let coverage = coverage();
+        let compressed = CompressedCellCoverage::from(&coverage);
+        assert!(
+            coverage
+                .compacted_iter()
+                .map(|cell| cell.resolution())
+                .collect::<std::collections::HashSet<_>>()
+                .len()
+                > 1
+        );
+
+        assert_eq!(compressed.len(), coverage.len());
+        assert_eq!(
+            compressed.compacted_iter().collect::<Vec<_>>(),
+            coverage.compacted_iter().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            compressed
+                .uncompacted_iter(Resolution::Ten)
+                .collect::<Vec<_>>(),
+            coverage
+                .uncompacted_iter(Resolution::Ten)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            CellCoverage::from(&compressed)
+                .compacted_iter()
+                .collect::<Vec<_>>(),
+            coverage.compacted_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn membership() {
+        let coverage = coverage();
+        let compressed = CompressedCellCoverage::from(&coverage);
+        for cell in coverage.compacted_iter() {
+            assert!(compressed.contains(cell));
+            assert!(compressed.covers(cell));
+        }
+        for cell in coverage.uncompacted_iter(Resolution::Eleven).step_by(7) {
+            assert!(compressed.covers(cell));
+        }
+
+        let outside = LatLng::new(40.0, 9.0).unwrap().to_cell(Resolution::Ten);
+        assert!(!compressed.contains(outside));
+        assert!(!compressed.covers(outside));
+        // neighbour of the disk, sorting in between the contained cells
+        let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Ten);
+        for cell in center.grid_ring_fast(31).flatten() {
+            assert!(!compressed.covers(cell));
+        }
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Ten);
+        let mut coverage = CellCoverage::default();
+        for cell in center.grid_disk::<Vec<_>>(50) {
+            coverage.insert(cell);
+        }
+        let compressed = CompressedCellCoverage::from(&coverage);
+        assert_eq!(compressed.len(), coverage.len());
+        assert!(compressed.memory_size() * 3 < coverage.len() * size_of::<h3o::CellIndex>());
+    }
+}
@@ -1,10 +1,14 @@
+use std::cmp::Ordering;
+
 use ahash::HashSet;
+use geo_types::Rect;
 use h3o::{CellIndex, Resolution};
 
 #[cfg(feature = "rayon")]
 use rayon::prelude::{IntoParallelRefMutIterator, ParallelIterator};
 
-use crate::Error;
+use crate::summary::{cell_bounds, merge_bounds};
+use crate::{CoverageSummary, Error};
 
 /// A container for cells covering an area.
 ///
@@ -13,12 +17,16 @@ use crate::Error;
 ///
 /// This struct internally uses mostly `sort` instead of `sort_unstable` as the Vec to be sorted are
 /// often at least partially sorted.
-#[derive(Default)]
+#[derive(Clone, Debug, Default)]
 pub struct CellCoverage {
     pub(crate) modified_resolutions: [bool; 16],
 
     /// cells by their resolution. The index of the array is the resolution for the referenced vec
     pub(crate) cells_by_resolution: [Vec<CellIndex>; 16],
+
+    /// Length of the prefix of each vec in `cells_by_resolution` which is sorted and compacted.
+    /// Cells beyond the prefix have been inserted since the last compaction.
+    compacted_len: [usize; 16],
 }
 
 impl CellCoverage {
@@ -32,9 +40,14 @@ impl CellCoverage {
             if source.is_empty() {
                 continue;
             }
+            // the appended cells follow the compacted prefix, so they get compacted by the
+            // next call to `compact_incremental`
+            self.compacted_len[r_idx] = self.compacted_len[r_idx].min(sink.len());
             self.modified_resolutions[r_idx] = true;
             sink.append(source);
         }
+        other.compacted_len = [0; 16];
+        other.modified_resolutions = [false; 16];
     }
 
     /// check if the coverage covers the given cell.
@@ -55,6 +68,60 @@ impl CellCoverage {
         false
     }
 
+    /// Remove the area of the given cells from the coverage.
+    ///
+    /// Contained cells located within any of `cells` are removed. Contained cells which are
+    /// ancestors of any of `cells` are replaced by their descendants not overlapping with `cells`.
+    pub fn subtract(&mut self, cells: impl IntoIterator<Item = CellIndex>) {
+        let removed = cells.into_iter().collect::<HashSet<_>>();
+        if removed.is_empty() {
+            return;
+        }
+        self.compacted_len = [0; 16];
+        let ancestors_of_removed = removed
+            .iter()
+            .flat_map(|cell| {
+                Resolution::range(Resolution::Zero, cell.resolution())
+                    .filter(|r| *r < cell.resolution())
+                    .filter_map(|r| cell.parent(r))
+            })
+            .collect::<HashSet<_>>();
+
+        let mut to_split = Vec::new();
+        for (r_idx, cells) in self.cells_by_resolution.iter_mut().enumerate() {
+            let len_before = cells.len();
+            cells.retain(|cell| {
+                if Resolution::range(Resolution::Zero, cell.resolution())
+                    .filter_map(|r| cell.parent(r))
+                    .any(|ancestor| removed.contains(&ancestor))
+                {
+                    false
+                } else if ancestors_of_removed.contains(cell) {
+                    to_split.push(*cell);
+                    false
+                } else {
+                    true
+                }
+            });
+            if cells.len() != len_before {
+                self.modified_resolutions[r_idx] = true;
+            }
+        }
+
+        while let Some(cell) = to_split.pop() {
+            let Some(child_resolution) = cell.resolution().succ() else {
+                continue;
+            };
+            for child in cell.children(child_resolution) {
+                if ancestors_of_removed.contains(&child) {
+                    to_split.push(child);
+                } else if !removed.contains(&child) {
+                    self.insert(child);
+                }
+            }
+        }
+    }
+
     pub fn compact(&mut self) -> Result<(), Error> {
         self.dedup(false, false);
 
@@ -84,10 +151,89 @@ impl CellCoverage {
         }
 
         self.dedup(true, true);
+        self.mark_compacted();
+
+        Ok(())
+    }
+
+    /// Compact the coverage by only looking at the cells inserted since the last compaction.
+    ///
+    /// In contrast to [`CellCoverage::compact`], the cells compacted before are not sorted
+    /// and compacted again. Only the parents of the newly inserted cells are checked for
+    /// being completely covered, so this is much cheaper when repeatedly inserting a few
+    /// cells into a large coverage. The result is the same as with [`CellCoverage::compact`].
+    ///
+    /// After modifications other than [`CellCoverage::insert`] and [`CellCoverage::append`],
+    /// the next call compacts all cells of the coverage.
+    pub fn compact_incremental(&mut self) -> Result<(), Error> {
+        let mut new_cells: [Vec<CellIndex>; 16] = Default::default();
+        for (r_idx, cells) in self.cells_by_resolution.iter_mut().enumerate() {
+            new_cells[r_idx] = cells.split_off(self.compacted_len[r_idx]);
+        }
+
+        for r_idx in (0..16).rev() {
+            let mut added = std::mem::take(&mut new_cells[r_idx]);
+            added.sort_unstable();
+            added.dedup();
+            added.retain(|cell| {
+                self.cells_by_resolution[r_idx].binary_search(cell).is_err()
+                    && !self.covered_by_coarser(*cell)
+            });
+            if added.is_empty() {
+                continue;
+            }
+
+            // remove the descendants of the added cells
+            let resolution = Resolution::try_from(r_idx as u8)?;
+            for finer in self.cells_by_resolution[r_idx + 1..].iter_mut() {
+                remove_descendants(finer, &added, resolution);
+            }
+
+            let cells = &mut self.cells_by_resolution[r_idx];
+            *cells = merge_sorted(std::mem::take(cells), &added);
+
+            // replace complete sets of siblings by their parent
+            let Some(parent_resolution) = resolution.pred() else {
+                continue;
+            };
+            let mut complete_parents = added
+                .iter()
+                .filter_map(|cell| cell.parent(parent_resolution))
+                .collect::<Vec<_>>();
+            complete_parents.dedup();
+            complete_parents.retain(|parent| {
+                parent
+                    .children(resolution)
+                    .all(|child| cells.binary_search(&child).is_ok())
+            });
+            remove_descendants(cells, &complete_parents, parent_resolution);
+            new_cells[r_idx - 1].append(&mut complete_parents);
+        }
 
+        self.mark_compacted();
         Ok(())
     }
 
+    /// Check if any ancestor of `cell` is contained.
+    fn covered_by_coarser(&self, cell: CellIndex) -> bool {
+        Resolution::range(Resolution::Zero, cell.resolution())
+            .filter(|res| *res < cell.resolution())
+            .any(|res| {
+                let cells = &self.cells_by_resolution[usize::from(res)];
+                !cells.is_empty()
+                    && cell
+                        .parent(res)
+                        .is_some_and(|ancestor| cells.binary_search(&ancestor).is_ok())
+            })
+    }
+
+    fn mark_compacted(&mut self) {
+        for (r_idx, cells) in self.cells_by_resolution.iter().enumerate() {
+            self.compacted_len[r_idx] = cells.len();
+        }
+        self.modified_resolutions = [false; 16];
+    }
+
     pub fn compacted_iter(&self) -> impl Iterator<Item = CellIndex> + '_ {
         self.cells_by_resolution
             .iter()
@@ -127,6 +273,45 @@ impl CellCoverage {
         !self.cells_by_resolution.iter().any(|v| !v.is_empty())
     }
 
+    /// Number of contained cells by their resolution. The index of the array is the resolution.
+    pub fn len_by_resolution(&self) -> [usize; 16] {
+        std::array::from_fn(|r_idx| self.cells_by_resolution[r_idx].len())
+    }
+
+    /// Number of cells yielded by [`CellCoverage::uncompacted_iter`] for resolution `r`,
+    /// computed without enumerating the cells.
+    pub fn uncompacted_len(&self, r: Resolution) -> u64 {
+        let r_idx: usize = r.into();
+        self.cells_by_resolution[..=r_idx]
+            .iter()
+            .flatten()
+            .map(|cell| cell.children_count(r))
+            .sum()
+    }
+
+    /// Area covered by the cells in m².
+    ///
+    /// Uses the exact area of each contained cell, so compacted cells contribute the area of
+    /// the coarse cell. Duplicates and overlapping cells are counted multiple times, so the
+    /// coverage should be finalized before.
+    pub fn area_m2(&self) -> f64 {
+        self.compacted_iter().map(CellIndex::area_m2).sum()
+    }
+
+    /// Bounding box of the boundaries of the cells in degrees, with `x` being the longitude
+    /// and `y` being the latitude. `None` when the coverage is empty.
+    ///
+    /// Coverages crossing the antimeridian or containing a pole span all longitudes.
+    pub fn bounds(&self) -> Option<Rect<f64>> {
+        self.compacted_iter()
+            .fold(None, |bounds, cell| merge_bounds(bounds, cell_bounds(cell)))
+    }
+
+    /// Cell counts, area and bounds of the coverage.
+    pub fn summary(&self) -> CoverageSummary {
+        CoverageSummary::from(self)
+    }
+
     pub fn insert(&mut self, cell: CellIndex) {
         let idx: usize = cell.resolution().into();
         self.cells_by_resolution[idx].push(cell);
@@ -134,6 +319,7 @@ impl CellCoverage {
     }
 
     pub fn dedup(&mut self, shrink: bool, parents: bool) {
+        self.compacted_len = [0; 16];
         #[cfg(feature = "rayon")]
         let iter = self.cells_by_resolution.par_iter_mut();
 
@@ -180,6 +366,33 @@ impl CellCoverage {
         }
     }
 
+    /// Bring all contained cells to the given resolution.
+    ///
+    /// Coarser cells are replaced by their children, finer cells by their parent
+    /// at `resolution`. The latter may grow the covered area.
+    pub fn normalize(&mut self, resolution: Resolution) {
+        let r_idx: usize = resolution.into();
+        let mut normalized = Vec::new();
+        for (cells_r_idx, cells) in self.cells_by_resolution.iter_mut().enumerate() {
+            let cells = std::mem::take(cells);
+            match cells_r_idx.cmp(&r_idx) {
+                Ordering::Less => {
+                    normalized.extend(cells.into_iter().flat_map(|cell| cell.children(resolution)))
+                }
+                Ordering::Equal => normalized.extend(cells),
+                Ordering::Greater => {
+                    normalized.extend(cells.into_iter().filter_map(|cell| cell.parent(resolution)))
+                }
+            }
+        }
+        normalized.sort();
+        normalized.dedup();
+        self.cells_by_resolution[r_idx] = normalized;
+        self.compacted_len = [0; 16];
+        self.modified_resolutions = [false; 16];
+        self.modified_resolutions[r_idx] = true;
+    }
+
     pub fn finalize(&mut self, compact: bool) -> Result<(), Error> {
         if compact {
             self.compact()?;
@@ -189,3 +402,226 @@ impl CellCoverage {
         Ok(())
     }
 }
+
+/// Merge two sorted vecs of cells.
+fn merge_sorted(a: Vec<CellIndex>, b: &[CellIndex]) -> Vec<CellIndex> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut b_iter = b.iter().copied().peekable();
+    for cell in a {
+        while let Some(b_cell) = b_iter.next_if(|b_cell| *b_cell < cell) {
+            merged.push(b_cell);
+        }
+        merged.push(cell);
+    }
+    merged.extend(b_iter);
+    merged
+}
+
+/// Remove the descendants of the sorted `ancestors` of resolution `ancestor_resolution` from
+/// the sorted `cells` of a finer resolution.
+fn remove_descendants(
+    cells: &mut Vec<CellIndex>,
+    ancestors: &[CellIndex],
+    ancestor_resolution: Resolution,
+) {
+    // the descendants of a cell form a contiguous range of the sorted cells
+    let has_descendants = ancestors.iter().any(|ancestor| {
+        let start =
+            cells.partition_point(|cell| cell.parent(ancestor_resolution) < Some(*ancestor));
+        cells
+            .get(start)
+            .is_some_and(|cell| cell.parent(ancestor_resolution) == Some(*ancestor))
+    });
+    if has_descendants {
+        cells.retain(|cell| {
+            cell.parent(ancestor_resolution)
+                .is_none_or(|parent| ancestors.binary_search(&parent).is_err())
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use h3o::{CellIndex, LatLng, Resolution};
+
+    use crate::CellCoverage;
+
+    #[test]
+    fn test_subtract() {
+        let cell = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Six);
+        let removed = cell.center_child(Resolution::Eight).unwrap();
+
+        let mut coverage = CellCoverage::default();
+        coverage.insert(cell);
+        coverage.subtract([removed]);
+        coverage.compact().unwrap();
+
+        assert!(!coverage.covers(removed));
+        assert!(!coverage.covers(cell));
+        // 6 siblings at res 7 and 6 siblings at res 8
+        assert_eq!(coverage.len(), 12);
+        let expected_n_cells = cell.children_count(Resolution::Eight) - 1;
+        assert_eq!(
+            coverage.uncompacted_iter(Resolution::Eight).count() as u64,
+            expected_n_cells
+        );
+
+        // subtracting an ancestor removes everything
+        coverage.subtract([cell]);
+        assert!(coverage.is_empty());
+    }
+
+    #[test]
+    fn statistics() {
+        let coarse = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Six);
+        let fine = LatLng::new(47.0, 9.0).unwrap().to_cell(Resolution::Eight);
+        let mut coverage = CellCoverage::default();
+        assert_eq!(coverage.area_m2(), 0.0);
+        assert_eq!(coverage.bounds(), None);
+
+        coverage.insert(coarse);
+        coverage.insert(fine);
+        coverage.compact().unwrap();
+
+        let mut expected_len_by_resolution = [0; 16];
+        expected_len_by_resolution[6] = 1;
+        expected_len_by_resolution[8] = 1;
+        assert_eq!(coverage.len_by_resolution(), expected_len_by_resolution);
+
+        for r in [Resolution::Seven, Resolution::Eight, Resolution::Ten] {
+            assert_eq!(
+                coverage.uncompacted_len(r),
+                coverage.uncompacted_iter(r).count() as u64
+            );
+        }
+
+        assert_relative_eq!(coverage.area_m2(), coarse.area_m2() + fine.area_m2());
+        let uncompacted_area = coverage
+            .uncompacted_iter(Resolution::Eight)
+            .map(CellIndex::area_m2)
+            .sum::<f64>();
+        assert_relative_eq!(coverage.area_m2(), uncompacted_area, max_relative = 1e-3);
+
+        let bounds = coverage.bounds().unwrap();
+        for vertex in coarse.boundary().iter().chain(fine.boundary().iter()) {
+            assert!((bounds.min().x..=bounds.max().x).contains(&vertex.lng()));
+            assert!((bounds.min().y..=bounds.max().y).contains(&vertex.lat()));
+        }
+        assert!(bounds.min().y < 47.0 && bounds.max().y > 48.0);
+        assert!(bounds.min().x < 9.0 && bounds.max().x > 9.0);
+        assert!(bounds.width() < 1.0);
+
+        let summary = coverage.summary();
+        assert_eq!(summary.n_cells, 2);
+        assert_eq!(summary.n_cells_by_resolution, expected_len_by_resolution);
+        assert_eq!(summary.area_m2, coverage.area_m2());
+        assert_eq!(summary.bounds, Some(bounds));
+    }
+
+    #[test]
+    fn bounds_of_polar_cells() {
+        for (pole_lat, resolution) in [
+            (90.0, Resolution::Two),
+            (-90.0, Resolution::Two),
+            (90.0, Resolution::Zero),
+            (-90.0, Resolution::Zero),
+        ] {
+            let cell = LatLng::new(pole_lat, 0.0).unwrap().to_cell(resolution);
+            let mut coverage = CellCoverage::default();
+            coverage.insert(cell);
+
+            let bounds = coverage.bounds().unwrap();
+            assert_eq!((bounds.min().x, bounds.max().x), (-180.0, 180.0));
+            if pole_lat > 0.0 {
+                assert_eq!(bounds.max().y, 90.0);
+            } else {
+                assert_eq!(bounds.min().y, -90.0);
+            }
+            for vertex in cell.boundary().iter() {
+                assert!((bounds.min().y..=bounds.max().y).contains(&vertex.lat()));
+            }
+        }
+    }
+
+    fn assert_same_cells(a: &CellCoverage, b: &CellCoverage) {
+        assert_eq!(
+            a.compacted_iter().collect::<Vec<_>>(),
+            b.compacted_iter().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn compact_incremental_matches_compact() {
+        let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Nine);
+        let mut cells = center.grid_disk::<Vec<_>>(20);
+        // a coarse cell covering some of the cells inserted before
+        cells.push(center.parent(Resolution::Seven).unwrap());
+        // a duplicate and a pentagon with all its children
+        cells.push(center);
+        let pentagon = CellIndex::base_cells()
+            .find(|cell| cell.is_pentagon())
+            .unwrap()
+            .center_child(Resolution::Three)
+            .unwrap();
+        cells.extend(pentagon.children(Resolution::Four));
+
+        let mut incremental = CellCoverage::default();
+        let mut expected = CellCoverage::default();
+        for batch in cells.chunks(97) {
+            for cell in batch {
+                incremental.insert(*cell);
+                expected.insert(*cell);
+            }
+            incremental.compact_incremental().unwrap();
+            expected.compact().unwrap();
+            assert_same_cells(&incremental, &expected);
+        }
+        assert!(incremental.covers(pentagon));
+        assert!(incremental.compacted_iter().any(|cell| cell == pentagon));
+
+        // falls back to all cells after other modifications
+        let removed = center.center_child(Resolution::Ten).unwrap();
+        incremental.subtract([removed]);
+        expected.subtract([removed]);
+        incremental.compact_incremental().unwrap();
+        expected.compact().unwrap();
+        assert_same_cells(&incremental, &expected);
+    }
+
+    #[test]
+    fn compact_incremental_after_append() {
+        let center = LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Nine);
+        let mut disk = center.grid_disk::<Vec<_>>(3).into_iter();
+
+        let mut a = CellCoverage::default();
+        let mut b = CellCoverage::default();
+        a.insert(disk.next().unwrap());
+        a.compact_incremental().unwrap();
+        for cell in disk.by_ref().take(10) {
+            b.insert(cell);
+        }
+        b.compact_incremental().unwrap();
+
+        // the appended cells get compacted together with the cells of `a`
+        a.append(&mut b);
+        assert!(b.is_empty());
+        for cell in disk.by_ref().take(10) {
+            a.insert(cell);
+        }
+        a.compact_incremental().unwrap();
+
+        // `b` starts over after being emptied
+        let cell = disk.next().unwrap();
+        b.insert(cell);
+        b.compact_incremental().unwrap();
+        assert_eq!(b.compacted_iter().collect::<Vec<_>>(), vec![cell]);
+
+        let mut expected = CellCoverage::default();
+        for cell in center.grid_disk::<Vec<_>>(3).into_iter().take(21) {
+            expected.insert(cell);
+        }
+        expected.compact().unwrap();
+        assert_same_cells(&a, &expected);
+    }
+}
@@ -0,0 +1,317 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, Hash};
+
+use h3o::{CellIndex, Resolution};
+
+use crate::aggregation::{Aggregation, coarsen_finalized};
+use crate::{CellCoverage, CoverageSummary, Error};
+
+/// A cell covered by the coverages of multiple values.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Overlap<K> {
+    /// The overlapping cell. When coverages overlap with cells of differing resolutions, this
+    /// is the finer of the cells.
+    pub cell: CellIndex,
+
+    /// The values whose coverages contain the cell.
+    pub values: Vec<K>,
+}
+
+/// Policy deciding which value keeps a cell covered by the coverages of multiple values.
+///
+/// The coverages of all other values get the cell removed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OverlapPolicy<K> {
+    /// The lowest of the values wins.
+    LowestWins,
+
+    /// The value listed first wins. Values not contained in the list lose against all
+    /// listed values, between them the lowest value wins.
+    Priority(Vec<K>),
+
+    /// The value covering the overlap with the finest cell wins, coarser cells of the other
+    /// values are reduced to their parts outside the overlap. Between cells of the same
+    /// resolution the lowest value wins.
+    FinerWins,
+}
+
+impl<K: Ord> OverlapPolicy<K> {
+    /// Compare the claims of two values to a cell. The claim which is ordered first wins.
+    fn compare(&self, a: &(&K, Resolution), b: &(&K, Resolution)) -> Ordering {
+        match self {
+            Self::LowestWins => a.0.cmp(b.0),
+            Self::Priority(priorities) => {
+                let rank = |value: &K| {
+                    priorities
+                        .iter()
+                        .position(|p| p == value)
+                        .unwrap_or(priorities.len())
+                };
+                rank(a.0).cmp(&rank(b.0)).then_with(|| a.0.cmp(b.0))
+            }
+            Self::FinerWins => b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)),
+        }
+    }
+}
+
+/// Operations on maps of values to their [`CellCoverage`], as returned by
+/// [`H3Converter::to_h3`](crate::H3Converter::to_h3).
+pub trait CellCoverageMap {
+    type Value;
+
+    /// Resample the map to the coarser `resolution`.
+    ///
+    /// Each parent cell gets the value chosen by `aggregation` from the values of its
+    /// children. Use [`Categorical`](crate::aggregation::Categorical) for a majority
+    /// resampling of classified rasters.
+    ///
+    /// With `compact` the returned coverages are compacted, otherwise they only contain
+    /// cells at `resolution`.
+    fn coarsen<A>(
+        &self,
+        resolution: Resolution,
+        aggregation: &A,
+        compact: bool,
+    ) -> Result<ahash::HashMap<Self::Value, CellCoverage>, Error>
+    where
+        A: Aggregation<Self::Value> + ?Sized;
+
+    /// Find all cells covered by the coverages of more than one value.
+    ///
+    /// The overlaps are ordered by their cell.
+    fn overlaps(&self) -> Vec<Overlap<Self::Value>>;
+
+    /// Resolve all overlaps between the coverages of the values using the given `policy`.
+    ///
+    /// Returns the overlaps found before resolving them.
+    fn resolve_overlaps(
+        &mut self,
+        policy: &OverlapPolicy<Self::Value>,
+    ) -> Vec<Overlap<Self::Value>>
+    where
+        Self::Value: Ord;
+
+    /// Cell counts, area and bounds of all coverages together.
+    ///
+    /// Cells contained in the coverages of multiple values are counted for each of them.
+    fn summary(&self) -> CoverageSummary;
+}
+
+impl<K, S> CellCoverageMap for HashMap<K, CellCoverage, S>
+where
+    K: Clone + Eq + Hash,
+    S: BuildHasher,
+{
+    type Value = K;
+
+    fn coarsen<A>(
+        &self,
+        resolution: Resolution,
+        aggregation: &A,
+        compact: bool,
+    ) -> Result<ahash::HashMap<Self::Value, CellCoverage>, Error>
+    where
+        A: Aggregation<Self::Value> + ?Sized,
+    {
+        coarsen_finalized(self, resolution, aggregation, compact)
+    }
+
+    fn overlaps(&self) -> Vec<Overlap<Self::Value>> {
+        find_overlaps(self)
+            .into_iter()
+            .map(|(cell, claims)| Overlap {
+                cell,
+                values: claims.into_iter().map(|(value, _)| value.clone()).collect(),
+            })
+            .collect()
+    }
+
+    fn resolve_overlaps(&mut self, policy: &OverlapPolicy<Self::Value>) -> Vec<Overlap<Self::Value>>
+    where
+        Self::Value: Ord,
+    {
+        let mut overlaps = Vec::new();
+        let mut removals = ahash::HashMap::<K, Vec<CellIndex>>::default();
+        for (cell, mut claims) in find_overlaps(self) {
+            claims.sort_unstable_by(|a, b| policy.compare(a, b));
+            for (value, _) in claims.iter().skip(1) {
+                removals.entry((*value).clone()).or_default().push(cell);
+            }
+            overlaps.push(Overlap {
+                cell,
+                values: claims.into_iter().map(|(value, _)| value.clone()).collect(),
+            });
+        }
+
+        for (value, cells) in removals {
+            if let Some(coverage) = self.get_mut(&value) {
+                coverage.subtract(cells);
+                coverage.dedup(true, false);
+            }
+        }
+        overlaps
+    }
+
+    fn summary(&self) -> CoverageSummary {
+        self.values()
+            .fold(CoverageSummary::default(), |mut summary, coverage| {
+                summary.merge(&coverage.summary());
+                summary
+            })
+    }
+}
+
+/// Find the cells covered by multiple values together with the claims of the values. A claim
+/// consists of the value and the resolution of the cell of its coverage containing the overlap.
+fn find_overlaps<K, S>(
+    map: &HashMap<K, CellCoverage, S>,
+) -> BTreeMap<CellIndex, Vec<(&K, Resolution)>>
+where
+    K: Eq + Hash,
+    S: BuildHasher,
+{
+    let mut values_by_cell = ahash::HashMap::<CellIndex, Vec<&K>>::default();
+    for (value, coverage) in map.iter() {
+        for cell in coverage.compacted_iter() {
+            values_by_cell.entry(cell).or_default().push(value);
+        }
+    }
+
+    let mut overlaps = BTreeMap::<CellIndex, Vec<(&K, Resolution)>>::new();
+    for (value, coverage) in map.iter() {
+        for cell in coverage.compacted_iter() {
+            // the cell itself and all of its ancestors
+            for resolution in Resolution::range(Resolution::Zero, cell.resolution()) {
+                let Some(other_values) = cell
+                    .parent(resolution)
+                    .and_then(|ancestor| values_by_cell.get(&ancestor))
+                else {
+                    continue;
+                };
+                for other_value in other_values.iter().filter(|v| **v != value) {
+                    let claims = overlaps.entry(cell).or_default();
+                    for claim in [(value, cell.resolution()), (*other_value, resolution)] {
+                        if !claims.iter().any(|(v, _)| *v == claim.0) {
+                            claims.push(claim);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    overlaps
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use h3o::{CellIndex, LatLng, Resolution};
+
+    use crate::{CellCoverage, CellCoverageMap, CoverageSummary, OverlapPolicy};
+
+    fn coarse_cell() -> CellIndex {
+        LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Six)
+    }
+
+    /// value 1 covers a cell at res 6, value 2 covers one of its descendants at res 8 and
+    /// value 3 covers exactly the same cell as value 1.
+    fn overlapping_map() -> HashMap<u8, CellCoverage> {
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        map.entry(1).or_default().insert(coarse_cell());
+        map.entry(2)
+            .or_default()
+            .insert(coarse_cell().center_child(Resolution::Eight).unwrap());
+        map.entry(3).or_default().insert(coarse_cell());
+        map
+    }
+
+    fn cells_of(map: &HashMap<u8, CellCoverage>, value: u8) -> Vec<CellIndex> {
+        let mut cells = map[&value].compacted_iter().collect::<Vec<_>>();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn test_overlaps() {
+        let map = overlapping_map();
+        let overlaps = map.overlaps();
+        assert_eq!(overlaps.len(), 2);
+
+        let fine_cell = coarse_cell().center_child(Resolution::Eight).unwrap();
+        let coarse_overlap = overlaps.iter().find(|o| o.cell == coarse_cell()).unwrap();
+        let mut values = coarse_overlap.values.clone();
+        values.sort_unstable();
+        assert_eq!(values, vec![1, 3]);
+
+        let fine_overlap = overlaps.iter().find(|o| o.cell == fine_cell).unwrap();
+        let mut values = fine_overlap.values.clone();
+        values.sort_unstable();
+        assert_eq!(values, vec![1, 2, 3]);
+
+        let mut no_overlaps = map.clone();
+        no_overlaps.remove(&1);
+        no_overlaps.remove(&3);
+        assert!(no_overlaps.overlaps().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_overlaps_lowest_wins() {
+        let mut map = overlapping_map();
+        let overlaps = map.resolve_overlaps(&OverlapPolicy::LowestWins);
+        assert_eq!(overlaps.len(), 2);
+        assert!(map.overlaps().is_empty());
+
+        assert_eq!(cells_of(&map, 1), vec![coarse_cell()]);
+        assert!(map[&2].is_empty());
+        assert!(map[&3].is_empty());
+    }
+
+    #[test]
+    fn test_summary() {
+        let map = overlapping_map();
+        let summary = map.summary();
+        assert_eq!(summary.n_cells, 3);
+        assert_eq!(summary.n_cells_by_resolution[6], 2);
+        assert_eq!(summary.n_cells_by_resolution[8], 1);
+        assert_eq!(
+            summary.area_m2,
+            map[&1].area_m2() + map[&2].area_m2() + map[&3].area_m2()
+        );
+        assert_eq!(summary.bounds, map[&1].bounds());
+
+        assert_eq!(
+            HashMap::<u8, CellCoverage>::default().summary(),
+            CoverageSummary::default()
+        );
+    }
+
+    #[test]
+    fn test_resolve_overlaps_priority() {
+        let mut map = overlapping_map();
+        map.resolve_overlaps(&OverlapPolicy::Priority(vec![3]));
+        assert!(map.overlaps().is_empty());
+
+        assert_eq!(cells_of(&map, 3), vec![coarse_cell()]);
+        assert!(map[&1].is_empty());
+        assert!(map[&2].is_empty());
+    }
+
+    #[test]
+    fn test_resolve_overlaps_finer_wins() {
+        let mut map = overlapping_map();
+        map.resolve_overlaps(&OverlapPolicy::FinerWins);
+        assert!(map.overlaps().is_empty());
+
+        let fine_cell = coarse_cell().center_child(Resolution::Eight).unwrap();
+        assert_eq!(cells_of(&map, 2), vec![fine_cell]);
+        // value 1 keeps the coarse cell except the fine cell, value 3 loses the tie
+        assert!(map[&3].is_empty());
+        let coverage_1 = &map[&1];
+        assert!(!coverage_1.covers(fine_cell));
+        assert_eq!(
+            coverage_1.uncompacted_iter(Resolution::Eight).count() as u64,
+            coarse_cell().children_count(Resolution::Eight) - 1
+        );
+    }
+}
@@ -8,6 +8,12 @@ pub enum Error {
     #[error("Empty array")]
     EmptyArray,
 
+    #[error("Latitude {0} is outside of the valid range of [-90, 90]")]
+    LatitudeOutOfRange(f64),
+
+    #[error("Longitude wrap requires a north-up raster spanning 360 degrees of longitude")]
+    NotGlobal,
+
     #[error(transparent)]
     InvalidLatLng(#[from] h3o::error::InvalidLatLng),
 
@@ -19,4 +25,15 @@ pub enum Error {
 
     #[error(transparent)]
     CompactionError(#[from] h3o::error::CompactionError),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "rayon")]
+    #[error(transparent)]
+    ThreadPoolBuildError(#[from] rayon::ThreadPoolBuildError),
+
+    #[cfg(feature = "async")]
+    #[error("The conversion task has been cancelled")]
+    Cancelled,
 }
@@ -1,16 +1,39 @@
 #![doc = include_str!("../README.md")]
 
-pub use crate::array::{ArrayValue, H3Converter};
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+pub use crate::array::SparseDetection;
+pub use crate::array::{ArrayValue, H3Converter, LatitudeOverflow};
 pub use crate::axis::AxisOrder;
+pub use crate::cell_value_map::{CellValueMap, CellValueStorage, IntoIter};
+pub use crate::compressed::CompressedCellCoverage;
 pub use crate::coverage::CellCoverage;
+pub use crate::coverage_map::{CellCoverageMap, Overlap, OverlapPolicy};
 pub use crate::error::Error;
-pub use crate::resolution::ResolutionSearchMode;
+pub use crate::owned::OwnedH3Converter;
+pub use crate::resolution::{ResolutionRange, ResolutionSearchMode};
+pub use crate::sampling::Sampling;
+pub use crate::summary::CoverageSummary;
+pub use crate::transform::PixelRegistration;
 
+pub mod aggregation;
 mod array;
 mod axis;
+mod cell_value_map;
+mod compressed;
 mod coverage;
+mod coverage_map;
 mod error;
+mod neighbourhood;
+mod owned;
+pub mod patch;
 mod resolution;
+mod sampling;
 pub mod sphere;
+mod spill;
+#[cfg(feature = "async")]
+pub mod stream;
+mod summary;
+mod threads;
 pub mod transform;
 mod util;
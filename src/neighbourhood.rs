@@ -0,0 +1,211 @@
+use ahash::HashSet;
+use h3o::{CellIndex, Resolution};
+
+use crate::{CellCoverage, Error};
+
+/// Neighbourhood operations.
+///
+/// All of them work on the cells of a working `resolution`: Coarser cells are replaced by
+/// their children, finer cells by their parent, see [`CellCoverage::normalize`]. Two cells are
+/// neighbours when they share an edge. With `compact` the resulting coverages are compacted,
+/// otherwise they only contain cells at the working resolution.
+impl CellCoverage {
+    /// Expand the coverage by all cells within `k` grid steps.
+    pub fn buffer(&self, k: u32, resolution: Resolution, compact: bool) -> Result<Self, Error> {
+        let cells = self.working_cells(resolution);
+        let mut buffered = cells.clone();
+        for cell in boundary(&cells) {
+            buffered.extend(cell.grid_disk::<Vec<_>>(k));
+        }
+        from_cells(buffered, compact)
+    }
+
+    /// Shrink the coverage to the cells whose neighbourhood within `k` grid steps is
+    /// completely covered.
+    pub fn erode(&self, k: u32, resolution: Resolution, compact: bool) -> Result<Self, Error> {
+        let mut cells = self.working_cells(resolution);
+        if let Some(boundary_k) = k.checked_sub(1) {
+            // all cells within k steps of a cell outside the coverage are within
+            // k - 1 steps of a boundary cell
+            let eroded = boundary(&cells)
+                .flat_map(|cell| cell.grid_disk::<Vec<_>>(boundary_k))
+                .collect::<Vec<_>>();
+            for cell in eroded {
+                cells.remove(&cell);
+            }
+        }
+        from_cells(cells, compact)
+    }
+
+    /// The cells of the coverage with at least one neighbour outside the coverage.
+    pub fn boundary_cells(&self, resolution: Resolution, compact: bool) -> Result<Self, Error> {
+        let cells = self.working_cells(resolution);
+        from_cells(boundary(&cells), compact)
+    }
+
+    /// Split the coverage into its connected components.
+    ///
+    /// The components are ordered by their smallest cell.
+    pub fn connected_components(
+        &self,
+        resolution: Resolution,
+        compact: bool,
+    ) -> Result<Vec<Self>, Error> {
+        components(self.working_cells(resolution))
+            .into_iter()
+            .map(|component| from_cells(component, compact))
+            .collect()
+    }
+
+    /// The cells of the coverage at `resolution`.
+    pub(crate) fn working_cells(&self, resolution: Resolution) -> HashSet<CellIndex> {
+        let mut normalized = self.clone();
+        normalized.normalize(resolution);
+        normalized.into_compacted_iter().collect()
+    }
+}
+
+/// Split `cells` into connected components, each sorted. The components are ordered by
+/// their smallest cell.
+pub(crate) fn components(mut unvisited: HashSet<CellIndex>) -> Vec<Vec<CellIndex>> {
+    let mut components = Vec::new();
+    while let Some(start) = unvisited.iter().next().copied() {
+        unvisited.remove(&start);
+        let mut component = vec![start];
+        let mut to_visit = vec![start];
+        while let Some(cell) = to_visit.pop() {
+            for neighbour in neighbours(cell) {
+                if unvisited.remove(&neighbour) {
+                    component.push(neighbour);
+                    to_visit.push(neighbour);
+                }
+            }
+        }
+        component.sort_unstable();
+        components.push(component);
+    }
+    components.sort_unstable_by_key(|component| component[0]);
+    components
+}
+
+/// The neighbours of `cell` sharing an edge with it.
+fn neighbours(cell: CellIndex) -> impl Iterator<Item = CellIndex> {
+    cell.grid_disk::<Vec<_>>(1)
+        .into_iter()
+        .filter(move |neighbour| *neighbour != cell)
+}
+
+/// The cells with at least one neighbour not contained in `cells`.
+fn boundary(cells: &HashSet<CellIndex>) -> impl Iterator<Item = CellIndex> + '_ {
+    cells
+        .iter()
+        .filter(|cell| neighbours(**cell).any(|neighbour| !cells.contains(&neighbour)))
+        .copied()
+}
+
+pub(crate) fn from_cells(
+    cells: impl IntoIterator<Item = CellIndex>,
+    compact: bool,
+) -> Result<CellCoverage, Error> {
+    let mut coverage = CellCoverage::default();
+    for cell in cells {
+        coverage.insert(cell);
+    }
+    coverage.finalize(compact)?;
+    Ok(coverage)
+}
+
+#[cfg(test)]
+mod tests {
+    use h3o::{CellIndex, LatLng, Resolution};
+
+    use crate::CellCoverage;
+
+    fn center() -> CellIndex {
+        LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Eight)
+    }
+
+    fn disk(k: u32) -> CellCoverage {
+        let mut coverage = CellCoverage::default();
+        for cell in center().grid_disk::<Vec<_>>(k) {
+            coverage.insert(cell);
+        }
+        coverage.finalize(false).unwrap();
+        coverage
+    }
+
+    fn cells(coverage: &CellCoverage) -> Vec<CellIndex> {
+        coverage.compacted_iter().collect()
+    }
+
+    #[test]
+    fn buffer_and_erode_disks() {
+        let coverage = disk(5);
+        assert_eq!(
+            cells(&coverage.buffer(3, Resolution::Eight, false).unwrap()),
+            cells(&disk(8))
+        );
+        assert_eq!(
+            cells(&coverage.erode(3, Resolution::Eight, false).unwrap()),
+            cells(&disk(2))
+        );
+        assert_eq!(
+            cells(&coverage.erode(0, Resolution::Eight, false).unwrap()),
+            cells(&coverage)
+        );
+        assert!(
+            coverage
+                .erode(6, Resolution::Eight, false)
+                .unwrap()
+                .is_empty()
+        );
+
+        let boundary = coverage.boundary_cells(Resolution::Eight, false).unwrap();
+        let mut ring = center().grid_ring_fast(5).flatten().collect::<Vec<_>>();
+        ring.sort_unstable();
+        assert_eq!(cells(&boundary), ring);
+    }
+
+    #[test]
+    fn mixed_resolutions() {
+        // a compacted disk buffered at a finer resolution than some of its cells
+        let mut coverage = disk(10);
+        coverage.compact().unwrap();
+        assert!(coverage.len_by_resolution()[7] > 0);
+
+        let buffered = coverage.buffer(2, Resolution::Eight, true).unwrap();
+        let mut expected = disk(12);
+        expected.compact().unwrap();
+        assert_eq!(cells(&buffered), cells(&expected));
+
+        // finer cells are replaced by their parents
+        let mut fine = CellCoverage::default();
+        fine.insert(center().center_child(Resolution::Ten).unwrap());
+        let buffered = fine.buffer(1, Resolution::Eight, false).unwrap();
+        assert_eq!(cells(&buffered), cells(&disk(1)));
+    }
+
+    #[test]
+    fn connected_components() {
+        let mut coverage = disk(2);
+        // a separate component consisting of a ring
+        let other_center = center().grid_ring_fast(10).flatten().next().unwrap();
+        for cell in other_center.grid_ring_fast(1).flatten() {
+            coverage.insert(cell);
+        }
+        coverage.finalize(false).unwrap();
+
+        let components = coverage
+            .connected_components(Resolution::Eight, false)
+            .unwrap();
+        assert_eq!(components.len(), 2);
+        let mut lens = components.iter().map(CellCoverage::len).collect::<Vec<_>>();
+        lens.sort_unstable();
+        assert_eq!(lens, vec![6, 19]);
+        assert!(components[0].compacted_iter().min() < components[1].compacted_iter().min());
+        assert_eq!(
+            components.iter().map(CellCoverage::len).sum::<usize>(),
+            coverage.len()
+        );
+    }
+}
@@ -0,0 +1,304 @@
+use std::path::PathBuf;
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
+
+use ahash::HashMap;
+use geo::AffineTransform;
+use h3o::{CellIndex, Resolution};
+use ndarray::ArcArray2;
+use num_traits::ToPrimitive;
+
+use crate::array::LatitudeOverflow;
+use crate::threads::Threads;
+use crate::{
+    ArrayValue, AxisOrder, CellCoverage, CellValueMap, CellValueStorage, Error, H3Converter,
+    PixelRegistration, ResolutionRange, ResolutionSearchMode, Sampling,
+};
+
+/// Converts a two-dimensional array owned by the converter to H3 cells.
+///
+/// In contrast to [`H3Converter`], which borrows the array, the nodata value and the
+/// transform, this converter owns all of them and the returned maps are keyed by clones of
+/// the raster values. The results can be kept after the raster has been dropped, and the
+/// converter can be moved across threads.
+///
+/// The array is stored as an [`ArcArray2`], so passing an `ArcArray2` shares the data
+/// without copying it. An [`ndarray::Array2`] gets converted without copying as well.
+#[derive(Clone)]
+pub struct OwnedH3Converter<T>
+where
+    T: ArrayValue,
+{
+    arr: ArcArray2<T>,
+    nodata_value: Option<T>,
+    transform: AffineTransform<f64>,
+    axis_order: AxisOrder,
+    pixel_registration: PixelRegistration,
+    latitude_overflow: LatitudeOverflow,
+    wrap_longitude: bool,
+    threads: Threads,
+    memory_budget: Option<usize>,
+    spill_directory: Option<PathBuf>,
+}
+
+impl<T> OwnedH3Converter<T>
+where
+    T: ArrayValue + Clone,
+{
+    pub fn new(
+        arr: impl Into<ArcArray2<T>>,
+        nodata_value: Option<T>,
+        transform: AffineTransform<f64>,
+        axis_order: AxisOrder,
+    ) -> Self {
+        Self {
+            arr: arr.into(),
+            nodata_value,
+            transform,
+            axis_order,
+            pixel_registration: PixelRegistration::default(),
+            latitude_overflow: LatitudeOverflow::default(),
+            wrap_longitude: false,
+            threads: Threads::default(),
+            memory_budget: None,
+            spill_directory: None,
+        }
+    }
+
+    /// See [`H3Converter::with_pixel_registration`].
+    pub fn with_pixel_registration(mut self, pixel_registration: PixelRegistration) -> Self {
+        self.pixel_registration = pixel_registration;
+        self
+    }
+
+    /// See [`H3Converter::with_latitude_overflow`].
+    pub fn with_latitude_overflow(mut self, latitude_overflow: LatitudeOverflow) -> Self {
+        self.latitude_overflow = latitude_overflow;
+        self
+    }
+
+    /// See [`H3Converter::with_longitude_wrap`].
+    pub fn with_longitude_wrap(mut self, wrap_longitude: bool) -> Self {
+        self.wrap_longitude = wrap_longitude;
+        self
+    }
+
+    /// See [`H3Converter::with_num_threads`].
+    pub fn with_num_threads(mut self, num_threads: usize) -> Self {
+        self.threads = Threads::Count(num_threads);
+        self
+    }
+
+    /// See [`H3Converter::with_thread_pool`].
+    #[cfg(feature = "rayon")]
+    pub fn with_thread_pool(mut self, pool: Arc<rayon::ThreadPool>) -> Self {
+        self.threads = Threads::Pool(pool);
+        self
+    }
+
+    /// See [`H3Converter::with_memory_budget`].
+    pub fn with_memory_budget(mut self, memory_budget: usize) -> Self {
+        self.memory_budget = Some(memory_budget);
+        self
+    }
+
+    /// See [`H3Converter::with_spill_directory`].
+    pub fn with_spill_directory(mut self, spill_directory: impl Into<PathBuf>) -> Self {
+        self.spill_directory = Some(spill_directory.into());
+        self
+    }
+
+    /// The array to convert.
+    pub fn array(&self) -> &ArcArray2<T> {
+        &self.arr
+    }
+
+    /// Run `f` with a [`H3Converter`] borrowing the data of this converter.
+    pub(crate) fn with_converter<R>(&self, f: impl FnOnce(&H3Converter<'_, T>) -> R) -> R {
+        let view = self.arr.view();
+        let mut converter =
+            H3Converter::new(&view, &self.nodata_value, &self.transform, self.axis_order)
+                .with_pixel_registration(self.pixel_registration)
+                .with_latitude_overflow(self.latitude_overflow)
+                .with_longitude_wrap(self.wrap_longitude)
+                .with_threads(self.threads.clone());
+        if let Some(memory_budget) = self.memory_budget {
+            converter = converter.with_memory_budget(memory_budget);
+        }
+        if let Some(spill_directory) = &self.spill_directory {
+            converter = converter.with_spill_directory(spill_directory.clone());
+        }
+        f(&converter)
+    }
+
+    /// See [`H3Converter::nearest_h3_resolution`].
+    pub fn nearest_h3_resolution(
+        &self,
+        search_mode: ResolutionSearchMode,
+    ) -> Result<Resolution, Error> {
+        self.with_converter(|conv| conv.nearest_h3_resolution(search_mode))
+    }
+
+    /// See [`H3Converter::h3_resolution_range`].
+    pub fn h3_resolution_range(
+        &self,
+        search_mode: ResolutionSearchMode,
+        n_bands: usize,
+    ) -> Result<ResolutionRange, Error> {
+        self.with_converter(|conv| conv.h3_resolution_range(search_mode, n_bands))
+    }
+
+    /// Convert to a hashmap mapping the raster values to their `CellCoverage`.
+    ///
+    /// See [`H3Converter::to_h3`].
+    pub fn to_h3(
+        &self,
+        h3_resolution: Resolution,
+        compact: bool,
+    ) -> Result<HashMap<T, CellCoverage>, Error> {
+        self.with_converter(|conv| conv.to_h3(h3_resolution, compact).map(clone_keys))
+    }
+
+    /// See [`H3Converter::to_h3_adaptive`].
+    pub fn to_h3_adaptive(
+        &self,
+        search_mode: ResolutionSearchMode,
+        compact: bool,
+    ) -> Result<HashMap<T, CellCoverage>, Error> {
+        self.with_converter(|conv| conv.to_h3_adaptive(search_mode, compact).map(clone_keys))
+    }
+
+    /// See [`H3Converter::to_cell_value_map`].
+    pub fn to_cell_value_map(
+        &self,
+        h3_resolution: Resolution,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<T>, Error> {
+        self.with_converter(|conv| {
+            conv.to_cell_value_map(h3_resolution, storage)
+                .map(|map| clone_values(map, storage))
+        })
+    }
+
+    /// See [`H3Converter::to_cell_value_map_numeric`].
+    pub fn to_cell_value_map_numeric(
+        &self,
+        h3_resolution: Resolution,
+        sampling: Sampling,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<f64>, Error>
+    where
+        T: ToPrimitive,
+    {
+        self.with_converter(|conv| conv.to_cell_value_map_numeric(h3_resolution, sampling, storage))
+    }
+
+    /// See [`H3Converter::sample_cells`].
+    pub fn sample_cells(
+        &self,
+        cells: impl IntoIterator<Item = CellIndex>,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<T>, Error> {
+        self.with_converter(|conv| {
+            conv.sample_cells(cells, storage)
+                .map(|map| clone_values(map, storage))
+        })
+    }
+
+    /// See [`H3Converter::sample_cells_numeric`].
+    pub fn sample_cells_numeric(
+        &self,
+        cells: impl IntoIterator<Item = CellIndex>,
+        sampling: Sampling,
+        storage: CellValueStorage,
+    ) -> Result<CellValueMap<f64>, Error>
+    where
+        T: ToPrimitive,
+    {
+        self.with_converter(|conv| conv.sample_cells_numeric(cells, sampling, storage))
+    }
+}
+
+pub(crate) fn clone_keys<T>(map: HashMap<&T, CellCoverage>) -> HashMap<T, CellCoverage>
+where
+    T: ArrayValue + Clone,
+{
+    map.into_iter()
+        .map(|(value, coverage)| (value.clone(), coverage))
+        .collect()
+}
+
+fn clone_values<T>(map: CellValueMap<&T>, storage: CellValueStorage) -> CellValueMap<T>
+where
+    T: Clone,
+{
+    CellValueMap::from_cells(
+        map.into_iter().map(|(cell, value)| (cell, value.clone())),
+        storage,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use h3o::Resolution;
+    use ndarray::Array2;
+
+    use crate::{AxisOrder, CellValueStorage, H3Converter, OwnedH3Converter};
+
+    fn array() -> Array2<u8> {
+        Array2::from_shape_fn((60, 80), |(r, c)| ((r / 20) * 4 + c / 20) as u8)
+    }
+
+    fn transform() -> geo::AffineTransform<f64> {
+        crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01])
+    }
+
+    #[test]
+    fn matches_borrowed_converter() {
+        let arr = array();
+        let transform = transform();
+        let view = arr.view();
+        let expected = H3Converter::new(&view, &Some(0), &transform, AxisOrder::YX)
+            .to_h3(Resolution::Seven, true)
+            .unwrap();
+
+        let owned = OwnedH3Converter::new(arr.clone(), Some(0), transform, AxisOrder::YX)
+            .to_h3(Resolution::Seven, true)
+            .unwrap();
+        assert_eq!(owned.len(), expected.len());
+        for (value, coverage) in expected.iter() {
+            assert_eq!(
+                owned[*value].compacted_iter().collect::<Vec<_>>(),
+                coverage.compacted_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn results_outlive_the_array() {
+        // the converter and the array get moved into another thread and dropped there
+        let converter = OwnedH3Converter::new(array(), Some(0), transform(), AxisOrder::YX);
+        let (h3_map, cell_value_map) = std::thread::spawn(move || {
+            (
+                converter.to_h3(Resolution::Seven, false).unwrap(),
+                converter
+                    .to_cell_value_map(Resolution::Seven, CellValueStorage::Hash)
+                    .unwrap(),
+            )
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(h3_map.len(), 11);
+        assert_eq!(
+            h3_map
+                .values()
+                .map(|coverage| coverage.len())
+                .sum::<usize>(),
+            cell_value_map.len()
+        );
+        for (cell, value) in cell_value_map.iter() {
+            assert!(h3_map[value].covers(cell));
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Patch analysis of classified rasters.
+//!
+//! The functions in this module operate on the value -> [`CellCoverage`] maps returned by
+//! [`H3Converter::to_h3`](crate::H3Converter::to_h3). A patch is a connected component of
+//! the coverage of a value, with cells being connected when they share an edge.
+use std::hash::{BuildHasher, Hash};
+
+use ahash::HashMap;
+use h3o::{CellIndex, Resolution};
+
+use crate::neighbourhood::{components, from_cells};
+use crate::{CellCoverage, Error};
+
+/// A connected component of the coverage of a value.
+#[derive(Clone, Debug)]
+pub struct Patch {
+    /// The cells of the patch.
+    pub coverage: CellCoverage,
+
+    /// Area of the patch in m².
+    pub area_m2: f64,
+
+    /// Length of the edges between the patch and the cells outside of it in meters. This
+    /// includes the edges along holes within the patch.
+    pub perimeter_m: f64,
+}
+
+/// The patches of a value.
+#[derive(Clone, Debug, Default)]
+pub struct ClassPatches {
+    /// The patches, ordered by their smallest cell.
+    pub patches: Vec<Patch>,
+}
+
+impl ClassPatches {
+    /// Number of patches.
+    pub fn len(&self) -> usize {
+        self.patches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patches.is_empty()
+    }
+
+    /// Area of all patches in m².
+    pub fn area_m2(&self) -> f64 {
+        self.patches.iter().map(|patch| patch.area_m2).sum()
+    }
+
+    /// The patch with the largest area.
+    pub fn largest(&self) -> Option<&Patch> {
+        self.patches
+            .iter()
+            .max_by(|a, b| a.area_m2.total_cmp(&b.area_m2))
+    }
+}
+
+/// Find the patches of all values.
+///
+/// The patches are labelled at the working `resolution`, to which cells of other resolutions
+/// get normalized, see [`CellCoverage::normalize`]. With `compact` the coverages of the
+/// patches are compacted, otherwise they only contain cells at `resolution`. The area of a
+/// patch is the exact area of its cells, see [`CellCoverage::area_m2`].
+pub fn patches<K, S>(
+    map: &std::collections::HashMap<K, CellCoverage, S>,
+    resolution: Resolution,
+    compact: bool,
+) -> Result<HashMap<K, ClassPatches>, Error>
+where
+    K: Clone + Eq + Hash,
+    S: BuildHasher,
+{
+    map.iter()
+        .map(|(value, coverage)| {
+            let patches = components(coverage.working_cells(resolution))
+                .into_iter()
+                .map(|cells| patch(cells, compact))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((value.clone(), ClassPatches { patches }))
+        })
+        .collect()
+}
+
+/// Build the patch of the sorted `cells` of a connected component.
+fn patch(cells: Vec<CellIndex>, compact: bool) -> Result<Patch, Error> {
+    let perimeter_m = cells
+        .iter()
+        .flat_map(|cell| cell.edges())
+        .filter(|edge| cells.binary_search(&edge.destination()).is_err())
+        .map(|edge| edge.length_m())
+        .sum();
+    let coverage = from_cells(cells, compact)?;
+    Ok(Patch {
+        area_m2: coverage.area_m2(),
+        perimeter_m,
+        coverage,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use approx::assert_relative_eq;
+    use h3o::{CellIndex, LatLng, Resolution};
+
+    use crate::CellCoverage;
+    use crate::patch::patches;
+
+    fn center() -> CellIndex {
+        LatLng::new(48.0, 9.0).unwrap().to_cell(Resolution::Eight)
+    }
+
+    #[test]
+    fn patches_of_classes() {
+        let mut map = HashMap::<u8, CellCoverage>::default();
+        // value 1: a disk and a single cell
+        let far_cell = center().grid_ring_fast(10).flatten().next().unwrap();
+        for cell in center()
+            .grid_disk::<Vec<_>>(2)
+            .into_iter()
+            .chain([far_cell])
+        {
+            map.entry(1).or_default().insert(cell);
+        }
+        // value 2: a ring around the disk of value 1
+        for cell in center().grid_ring_fast(3).flatten() {
+            map.entry(2).or_default().insert(cell);
+        }
+        for coverage in map.values_mut() {
+            coverage.compact().unwrap();
+        }
+
+        let patches = patches(&map, Resolution::Eight, true).unwrap();
+        assert_eq!(patches.len(), 2);
+
+        let patches_1 = &patches[&1];
+        assert_eq!(patches_1.len(), 2);
+        assert_relative_eq!(patches_1.area_m2(), map[&1].area_m2());
+        let largest = patches_1.largest().unwrap();
+        assert_eq!(largest.coverage.uncompacted_len(Resolution::Eight), 19);
+
+        let single = patches_1
+            .patches
+            .iter()
+            .find(|patch| patch.coverage.len() == 1)
+            .unwrap();
+        assert_relative_eq!(single.area_m2, far_cell.area_m2());
+        let far_cell_perimeter = far_cell.edges().map(|edge| edge.length_m()).sum::<f64>();
+        assert_relative_eq!(single.perimeter_m, far_cell_perimeter);
+
+        // the ring has edges to the inside and to the outside
+        let patches_2 = &patches[&2];
+        assert_eq!(patches_2.len(), 1);
+        let ring = &patches_2.patches[0];
+        assert_eq!(ring.coverage.len(), 18);
+        assert!(format!("{patches_2:?}").contains("perimeter_m"));
+        assert!(ring.perimeter_m > largest.perimeter_m * 2.0);
+    }
+}
@@ -1,7 +1,9 @@
-use geo::{AffineOps, AffineTransform};
-use geo_types::Rect;
+use geo::{AffineOps, AffineTransform, Area};
+use geo_types::{Rect, coord};
 use h3o::{LatLng, Resolution};
 
+use crate::transform::PixelRegistration;
+use crate::util::clip_to_valid_latitudes;
 use crate::{AxisOrder, error::Error, sphere::AreaOnSphere};
 
 #[derive(Copy, Clone)]
@@ -14,14 +16,55 @@ pub enum ResolutionSearchMode {
     SmallerThanPixel,
 }
 
+/// The range of H3 resolutions suitable for the pixels of a raster.
+///
+/// Returned by [`ResolutionSearchMode::h3_resolution_range`], which samples multiple row bands of
+/// the raster instead of only its center. This matters for rasters in geographic coordinates
+/// covering large latitude ranges, where the area of a pixel varies by orders of magnitude.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResolutionRange {
+    /// The coarsest resolution found for any of the sampled bands.
+    pub min: Resolution,
+
+    /// The median of the resolutions found for the sampled bands.
+    pub median: Resolution,
+
+    /// The finest resolution found for any of the sampled bands. This is the resolution
+    /// required to not lose detail anywhere in the raster.
+    pub max: Resolution,
+}
+
 impl ResolutionSearchMode {
     /// Find the H3 resolution closed to the size of a pixel in an array
     /// of the given shape with the given transform.
+    ///
+    /// The pixel and cell areas are only compared at the center of the array. See
+    /// [`ResolutionSearchMode::h3_resolution_range`] for rasters spanning large latitude ranges.
+    ///
+    /// The transform is expected to refer to the upper left corners of the pixels, see
+    /// [`ResolutionSearchMode::nearest_h3_resolution_with_registration`] for other registrations.
     pub fn nearest_h3_resolution(
         &self,
         shape: [usize; 2],
         transform: &AffineTransform<f64>,
         axis_order: &AxisOrder,
+    ) -> Result<Resolution, Error> {
+        self.nearest_h3_resolution_with_registration(
+            shape,
+            transform,
+            axis_order,
+            PixelRegistration::Area,
+        )
+    }
+
+    /// Like [`ResolutionSearchMode::nearest_h3_resolution`], with the transform referring to
+    /// the pixels according to `pixel_registration`.
+    pub fn nearest_h3_resolution_with_registration(
+        &self,
+        shape: [usize; 2],
+        transform: &AffineTransform<f64>,
+        axis_order: &AxisOrder,
+        pixel_registration: PixelRegistration,
     ) -> Result<Resolution, Error> {
         if shape[0] == 0 || shape[1] == 0 {
             return Err(Error::EmptyArray);
@@ -31,10 +74,70 @@ impl ResolutionSearchMode {
         // Use the full pixel footprint (outer corners of the first and last
         // pixels) rather than `(shape - 1)` so the per-pixel area is correct,
         // including for small and 1x1 arrays.
-        let bbox_array = Rect::new((0.0_f64, 0.0_f64), (x_size as f64, y_size as f64))
-            .affine_transform(transform);
-        let area_pixel = bbox_array.area_on_sphere_m2() / (x_size * y_size) as f64;
-        let center_of_array: LatLng = bbox_array.center().try_into()?;
+        self.nearest_h3_resolution_for_window(
+            Rect::new((0.0_f64, 0.0_f64), (x_size as f64, y_size as f64)),
+            &pixel_registration.to_area_transform(transform),
+        )
+    }
+
+    /// Find the range of H3 resolutions suitable for the pixels of an array of the given shape
+    /// with the given transform.
+    ///
+    /// The array is split into `n_bands` bands of rows, and the resolution is determined
+    /// for each of these bands separately. `n_bands` is clamped to the number of rows, so
+    /// passing `usize::MAX` samples every single row.
+    pub fn h3_resolution_range(
+        &self,
+        shape: [usize; 2],
+        transform: &AffineTransform<f64>,
+        axis_order: &AxisOrder,
+        pixel_registration: PixelRegistration,
+        n_bands: usize,
+    ) -> Result<ResolutionRange, Error> {
+        if shape[0] == 0 || shape[1] == 0 {
+            return Err(Error::EmptyArray);
+        }
+        let x_size = shape[axis_order.x_axis()];
+        let y_size = shape[axis_order.y_axis()];
+        let n_bands = n_bands.clamp(1, y_size);
+        let transform = pixel_registration.to_area_transform(transform);
+
+        let mut resolutions = (0..n_bands)
+            .filter_map(|band_i| {
+                let y_min = band_i * y_size / n_bands;
+                let y_max = (band_i + 1) * y_size / n_bands;
+                let band = Rect::new((0.0_f64, y_min as f64), (x_size as f64, y_max as f64));
+                // skip bands located completely beyond the poles
+                pixel_area_m2(band, &transform)?;
+                Some(self.nearest_h3_resolution_for_window(band, &transform))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if resolutions.is_empty() {
+            return Err(Error::LatitudeOutOfRange(transform.yoff()));
+        }
+        resolutions.sort_unstable();
+
+        Ok(ResolutionRange {
+            min: resolutions[0],
+            median: resolutions[resolutions.len() / 2],
+            max: resolutions[resolutions.len() - 1],
+        })
+    }
+
+    /// Find the H3 resolution closest to the size of the pixels within `window`.
+    ///
+    /// `window` is given in array coordinates and must span at least one pixel
+    /// in both dimensions.
+    pub(crate) fn nearest_h3_resolution_for_window(
+        &self,
+        window: Rect<f64>,
+        transform: &AffineTransform<f64>,
+    ) -> Result<Resolution, Error> {
+        let area_pixel = pixel_area_m2(window, transform)
+            .ok_or_else(|| Error::LatitudeOutOfRange(transform.apply(window.center()).y))?;
+        let center = transform.apply(window.center());
+        let center_of_window: LatLng =
+            coord! {x: center.x, y: center.y.clamp(-90.0, 90.0)}.try_into()?;
 
         // Default to the finest resolution. This is the correct result for
         // `SmallerThanPixel` when no H3 cell is smaller than the pixel, and is
@@ -42,7 +145,7 @@ impl ResolutionSearchMode {
         let mut nearest_h3_res = Resolution::Fifteen;
         let mut area_difference = None;
         for h3_res in Resolution::range(Resolution::Zero, Resolution::Fifteen) {
-            let area_h3_index = center_of_array.to_cell(h3_res).area_m2();
+            let area_h3_index = center_of_window.to_cell(h3_res).area_m2();
 
             match self {
                 Self::SmallerThanPixel => {
@@ -78,12 +181,27 @@ impl ResolutionSearchMode {
     }
 }
 
+/// The mean area of the pixels within `window` in square meters.
+///
+/// The window is transformed as a polygon, so for rotated or sheared transforms the area
+/// of the true parallelograms is used instead of the area of their bounding boxes. Parts of
+/// the window located beyond the poles are left out. Returns `None` when the window is
+/// located completely beyond the poles.
+pub(crate) fn pixel_area_m2(window: Rect<f64>, transform: &AffineTransform<f64>) -> Option<f64> {
+    let inverse_transform = transform.inverse()?;
+    let window = clip_to_valid_latitudes(window.to_polygon().affine_transform(transform))?;
+    let n_pixels = window.affine_transform(&inverse_transform).unsigned_area();
+    Some(window.area_on_sphere_m2() / n_pixels)
+}
+
 #[cfg(test)]
 mod tests {
+    use approx::assert_relative_eq;
+    use geo_types::Rect;
     use h3o::Resolution;
 
-    use crate::AxisOrder;
-    use crate::resolution::ResolutionSearchMode;
+    use crate::resolution::{ResolutionSearchMode, pixel_area_m2};
+    use crate::{AxisOrder, PixelRegistration};
 
     #[test]
     fn test_nearest_h3_resolution() {
@@ -139,4 +257,136 @@ mod tests {
         // the result must not be res 0.
         assert_ne!(h3_res, Resolution::Zero);
     }
+
+    #[test]
+    fn test_h3_resolution_range_global() {
+        // global raster with 1 degree pixels. Pixels near the poles are much smaller
+        // than pixels at the equator, so a single sample at the center is misleading.
+        let gt = crate::transform::from_gdal(&[-180.0, 1.0, 0.0, 90.0, 0.0, -1.0]);
+        let range = ResolutionSearchMode::MinDiff
+            .h3_resolution_range(
+                [180_usize, 360_usize],
+                &gt,
+                &AxisOrder::YX,
+                PixelRegistration::Area,
+                18,
+            )
+            .unwrap();
+        assert!(range.min < range.max);
+        assert!(range.min <= range.median && range.median <= range.max);
+
+        // the center of the raster is at the equator, where the pixels are largest
+        let h3_res_center = ResolutionSearchMode::MinDiff
+            .nearest_h3_resolution([180_usize, 360_usize], &gt, &AxisOrder::YX)
+            .unwrap();
+        assert_eq!(range.min, h3_res_center);
+    }
+
+    #[test]
+    fn test_h3_resolution_range_every_row() {
+        // transform of the included r.tiff. The raster spans less than 2.5 degrees of
+        // latitude, so all rows should end up with roughly the same resolution.
+        let gt = crate::transform::from_rasterio(&[
+            0.0011965049999999992,
+            0.0,
+            8.11377,
+            0.0,
+            -0.001215135,
+            49.40792,
+        ]);
+        let range = ResolutionSearchMode::SmallerThanPixel
+            .h3_resolution_range(
+                [2000_usize, 2000_usize],
+                &gt,
+                &AxisOrder::YX,
+                PixelRegistration::Area,
+                usize::MAX,
+            )
+            .unwrap();
+        assert_eq!(range.median, Resolution::Eleven);
+        assert!(u8::from(range.max) - u8::from(range.min) <= 1);
+    }
+
+    #[test]
+    fn test_nearest_h3_resolution_point_registration() {
+        // the registration only shifts the raster by half a pixel, the pixel size stays the same
+        let gt = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        for search_mode in [
+            ResolutionSearchMode::MinDiff,
+            ResolutionSearchMode::SmallerThanPixel,
+        ] {
+            let area = search_mode
+                .nearest_h3_resolution([100, 100], &gt, &AxisOrder::YX)
+                .unwrap();
+            let point = search_mode
+                .nearest_h3_resolution_with_registration(
+                    [100, 100],
+                    &gt,
+                    &AxisOrder::YX,
+                    PixelRegistration::Point,
+                )
+                .unwrap();
+            assert_eq!(area, point);
+        }
+    }
+
+    #[test]
+    fn test_pixel_area_rotated_and_sheared() {
+        let window = Rect::new((0.0, 0.0), (100.0, 100.0));
+        let north_up = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let area_north_up = pixel_area_m2(window, &north_up).unwrap();
+
+        // rotated by 30 degrees around the origin, the pixels keep their size
+        let (sin, cos) = 30.0_f64.to_radians().sin_cos();
+        let rotated = crate::transform::from_gdal(&[
+            8.0,
+            0.01 * cos,
+            0.01 * sin,
+            49.0,
+            0.01 * sin,
+            -0.01 * cos,
+        ]);
+        assert_relative_eq!(
+            pixel_area_m2(window, &rotated).unwrap(),
+            area_north_up,
+            max_relative = 0.01
+        );
+
+        // shearing along x keeps the area of the parallelograms
+        let sheared = crate::transform::from_gdal(&[8.0, 0.01, 0.005, 49.0, 0.0, -0.01]);
+        assert_relative_eq!(
+            pixel_area_m2(window, &sheared).unwrap(),
+            area_north_up,
+            max_relative = 0.01
+        );
+    }
+
+    #[test]
+    fn test_pixel_area_beyond_poles() {
+        // 0.5 degree pixels, the first 10 rows are located beyond the north pole
+        let gt = crate::transform::from_gdal(&[-180.0, 0.5, 0.0, 95.0, 0.0, -0.5]);
+        assert_relative_eq!(
+            pixel_area_m2(Rect::new((0.0, 0.0), (720.0, 20.0)), &gt).unwrap(),
+            pixel_area_m2(Rect::new((0.0, 10.0), (720.0, 20.0)), &gt).unwrap(),
+            max_relative = 1e-9
+        );
+        assert_eq!(
+            pixel_area_m2(Rect::new((0.0, 0.0), (720.0, 10.0)), &gt),
+            None
+        );
+
+        let range = ResolutionSearchMode::MinDiff
+            .h3_resolution_range([40, 720], &gt, &AxisOrder::YX, PixelRegistration::Area, 4)
+            .unwrap();
+        let range_clipped = ResolutionSearchMode::MinDiff
+            .h3_resolution_range(
+                [30, 720],
+                &crate::transform::from_gdal(&[-180.0, 0.5, 0.0, 90.0, 0.0, -0.5]),
+                &AxisOrder::YX,
+                PixelRegistration::Area,
+                3,
+            )
+            .unwrap();
+        assert_eq!(range, range_clipped);
+    }
 }
@@ -0,0 +1,475 @@
+use geo::{AffineOps, AffineTransform, BooleanOps};
+use geo_types::{Coord, LineString, Polygon, Rect, coord};
+use h3o::{CellIndex, LatLng};
+use ndarray::ArrayView2;
+use num_traits::ToPrimitive;
+
+use crate::sphere::AreaOnSphere;
+use crate::{ArrayValue, AxisOrder, Error};
+
+/// How the raster is sampled at the location of a cell.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Sampling {
+    /// The value of the pixel containing the centroid of the cell.
+    #[default]
+    Nearest,
+
+    /// Bilinear interpolation between the centers of the four pixels surrounding the
+    /// centroid of the cell. Pixels with nodata or outside the raster are left out and the
+    /// weights of the remaining pixels are renormalized.
+    Bilinear,
+
+    /// Bicubic convolution over the 4x4 pixels surrounding the centroid of the cell. Falls
+    /// back to [`Sampling::Bilinear`] when any of these pixels is nodata or outside the raster.
+    Bicubic,
+
+    /// The mean of the values of all pixels overlapping the cell, weighted by the area of the
+    /// overlap on the sphere. Pixels with nodata are left out.
+    ///
+    /// Each overlapping pixel gets intersected with the cell, so the cost grows with the
+    /// number of pixels per cell. Cells much coarser than the pixels are slow to sample; rather
+    /// sample at a resolution matching the raster and use [`aggregation`](crate::aggregation)
+    /// to derive coarser cells.
+    AreaWeighted,
+}
+
+/// Looks up the values of an array at the locations of cells.
+pub(crate) struct Sampler<'a, 'b, T> {
+    arr: &'a ArrayView2<'a, T>,
+    nodata_value: &'b Option<T>,
+    transform: &'b AffineTransform<f64>,
+    inverse_transform: AffineTransform<f64>,
+    axis_order: AxisOrder,
+    wrap_longitude: bool,
+}
+
+impl<'a, 'b, T> Sampler<'a, 'b, T>
+where
+    T: ArrayValue,
+{
+    pub(crate) fn new(
+        arr: &'a ArrayView2<'a, T>,
+        nodata_value: &'b Option<T>,
+        transform: &'b AffineTransform<f64>,
+        axis_order: AxisOrder,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            arr,
+            nodata_value,
+            transform,
+            inverse_transform: transform.inverse().ok_or(Error::TransformNotInvertible)?,
+            axis_order,
+            wrap_longitude: false,
+        })
+    }
+
+    /// Let the columns of the array wrap around, as for rasters spanning the whole globe.
+    pub(crate) fn with_longitude_wrap(mut self, wrap_longitude: bool) -> Self {
+        self.wrap_longitude = wrap_longitude;
+        self
+    }
+
+    fn size(&self) -> (f64, f64) {
+        (
+            self.arr.shape()[self.axis_order.x_axis()] as f64,
+            self.arr.shape()[self.axis_order.y_axis()] as f64,
+        )
+    }
+
+    /// The value of the pixel at the given array coordinates. `None` for pixels outside
+    /// the array and pixels with nodata.
+    fn pixel(&self, x: f64, y: f64) -> Option<&'a T> {
+        let x = if self.wrap_longitude {
+            x.rem_euclid(self.size().0)
+        } else {
+            x
+        };
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+        let arr_coord = match self.axis_order {
+            AxisOrder::XY => [x as usize, y as usize],
+            AxisOrder::YX => [y as usize, x as usize],
+        };
+        self.arr
+            .get(arr_coord)
+            .filter(|value| self.nodata_value.as_ref() != Some(*value))
+    }
+
+    /// Transform a geographical coordinate to array coordinates.
+    ///
+    /// For rasters extending beyond the antimeridian the longitude is shifted by 360 degrees
+    /// when this is required to reach the array. Returns the array coordinates together with
+    /// the applied longitude offset, or `None` when the coordinate is located outside the array.
+    fn to_array_coord(&self, coord: Coord) -> Option<(Coord, f64)> {
+        let (x_size, y_size) = self.size();
+        [0.0, 360.0, -360.0].into_iter().find_map(|lng_offset| {
+            let transformed = self
+                .inverse_transform
+                .apply(coord! {x: coord.x + lng_offset, y: coord.y});
+            ((self.wrap_longitude || (transformed.x >= 0.0 && transformed.x < x_size))
+                && transformed.y >= 0.0
+                && transformed.y < y_size)
+                .then_some((transformed, lng_offset))
+        })
+    }
+
+    /// The value of the pixel containing the centroid of the cell.
+    pub(crate) fn nearest(&self, cell: CellIndex) -> Option<&'a T> {
+        let (arr_coord, _) = self.to_array_coord(LatLng::from(cell).into())?;
+        self.pixel(arr_coord.x.floor(), arr_coord.y.floor())
+    }
+
+    pub(crate) fn sample(&self, cell: CellIndex, sampling: Sampling) -> Option<f64>
+    where
+        T: ToPrimitive,
+    {
+        match sampling {
+            Sampling::Nearest => self.nearest(cell).and_then(|value| value.to_f64()),
+            Sampling::Bilinear => self.bilinear(cell),
+            Sampling::Bicubic => self.bicubic(cell),
+            Sampling::AreaWeighted => self.area_weighted(cell),
+        }
+    }
+
+    fn bilinear(&self, cell: CellIndex) -> Option<f64>
+    where
+        T: ToPrimitive,
+    {
+        let (arr_coord, _) = self.to_array_coord(LatLng::from(cell).into())?;
+        // relative to the pixel centers
+        let (u, v) = (arr_coord.x - 0.5, arr_coord.y - 0.5);
+        let (x0, y0) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x0, v - y0);
+
+        weighted_mean([
+            (self.pixel(x0, y0), (1.0 - fx) * (1.0 - fy)),
+            (self.pixel(x0 + 1.0, y0), fx * (1.0 - fy)),
+            (self.pixel(x0, y0 + 1.0), (1.0 - fx) * fy),
+            (self.pixel(x0 + 1.0, y0 + 1.0), fx * fy),
+        ])
+    }
+
+    fn bicubic(&self, cell: CellIndex) -> Option<f64>
+    where
+        T: ToPrimitive,
+    {
+        let (arr_coord, _) = self.to_array_coord(LatLng::from(cell).into())?;
+        // relative to the pixel centers
+        let (u, v) = (arr_coord.x - 0.5, arr_coord.y - 0.5);
+        let (x0, y0) = (u.floor(), v.floor());
+        let (fx, fy) = (u - x0, v - y0);
+
+        let mut sum = 0.0;
+        for (j, wy) in cubic_weights(fy).into_iter().enumerate() {
+            for (i, wx) in cubic_weights(fx).into_iter().enumerate() {
+                let Some(value) = self.pixel(x0 + i as f64 - 1.0, y0 + j as f64 - 1.0) else {
+                    // incomplete neighbourhood at the edges of the raster or next to nodata
+                    return self.bilinear(cell);
+                };
+                sum += value.to_f64()? * wx * wy;
+            }
+        }
+        Some(sum)
+    }
+
+    fn area_weighted(&self, cell: CellIndex) -> Option<f64>
+    where
+        T: ToPrimitive,
+    {
+        let centroid: Coord = LatLng::from(cell).into();
+        let (_, lng_offset) = self.to_array_coord(centroid)?;
+
+        // unwrap the boundary around the centroid to handle cells crossing the antimeridian
+        let cell_polygon = Polygon::new(
+            LineString::from(
+                cell.boundary()
+                    .iter()
+                    .map(|ll| {
+                        let mut lng = ll.lng();
+                        if lng - centroid.x > 180.0 {
+                            lng -= 360.0;
+                        } else if centroid.x - lng > 180.0 {
+                            lng += 360.0;
+                        }
+                        coord! {x: lng + lng_offset, y: ll.lat()}
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            vec![],
+        );
+
+        let (x_size, y_size) = self.size();
+        let arr_vertices = cell_polygon
+            .exterior()
+            .coords()
+            .map(|c| self.inverse_transform.apply(*c))
+            .collect::<Vec<_>>();
+        let x_min = arr_vertices.iter().map(|c| c.x).fold(f64::MAX, f64::min);
+        let x_max = arr_vertices.iter().map(|c| c.x).fold(f64::MIN, f64::max);
+        let y_min = arr_vertices.iter().map(|c| c.y).fold(f64::MAX, f64::min);
+        let y_max = arr_vertices.iter().map(|c| c.y).fold(f64::MIN, f64::max);
+        let (x_min, x_max) = if self.wrap_longitude {
+            (x_min.floor(), x_max.floor())
+        } else {
+            (x_min.floor().max(0.0), x_max.floor().min(x_size - 1.0))
+        };
+        let (y_min, y_max) = (y_min.floor().max(0.0), y_max.floor().min(y_size - 1.0));
+
+        let mut weighted_values = Vec::new();
+        let mut y = y_min;
+        while y <= y_max {
+            let mut x = x_min;
+            while x <= x_max {
+                if let Some(value) = self.pixel(x, y) {
+                    let pixel_polygon =
+                        Rect::new(coord! {x: x, y: y}, coord! {x: x + 1.0, y: y + 1.0})
+                            .to_polygon()
+                            .affine_transform(self.transform);
+                    let overlap = cell_polygon
+                        .intersection(&pixel_polygon)
+                        .iter()
+                        .map(AreaOnSphere::area_on_sphere_m2)
+                        .sum();
+                    weighted_values.push((Some(value), overlap));
+                }
+                x += 1.0;
+            }
+            y += 1.0;
+        }
+        weighted_mean(weighted_values)
+    }
+}
+
+/// Weights of the four pixels at the distances `1 + t`, `t`, `1 - t` and `2 - t` from the
+/// sampled location using the cubic convolution kernel of Keys with `a = -0.5`.
+fn cubic_weights(t: f64) -> [f64; 4] {
+    const A: f64 = -0.5;
+    let near = |d: f64| ((A + 2.0) * d - (A + 3.0)) * d * d + 1.0;
+    let far = |d: f64| ((A * d - 5.0 * A) * d + 8.0 * A) * d - 4.0 * A;
+    [far(1.0 + t), near(t), near(1.0 - t), far(2.0 - t)]
+}
+
+/// The weighted mean of all present values. `None` when no value with a positive
+/// weight is present.
+fn weighted_mean<'a, T>(
+    weighted_values: impl IntoIterator<Item = (Option<&'a T>, f64)>,
+) -> Option<f64>
+where
+    T: ToPrimitive + 'a,
+{
+    let (sum, sum_weights) = weighted_values
+        .into_iter()
+        .filter_map(|(value, weight)| Some((value?.to_f64()?, weight)))
+        .fold(
+            (0.0, 0.0),
+            |(sum, sum_weights), (value, weight): (f64, f64)| {
+                (sum + value * weight, sum_weights + weight)
+            },
+        );
+    (sum_weights > 0.0).then(|| sum / sum_weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use approx::assert_relative_eq;
+    use geo::{BoundingRect, Contains};
+    use geo_types::{Coord, coord};
+    use h3o::{LatLng, Resolution};
+    use ordered_float::OrderedFloat;
+
+    use crate::sampling::{Sampler, Sampling};
+    use crate::{AxisOrder, CellValueStorage, H3Converter};
+
+    /// The value of each pixel is its column index.
+    fn column_gradient(width: usize, height: usize) -> ndarray::Array2<u16> {
+        ndarray::Array2::from_shape_fn((height, width), |(_, c)| c as u16)
+    }
+
+    #[test]
+    fn test_sample_nearest() {
+        let arr = column_gradient(50, 40);
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &None, &transform, AxisOrder::YX);
+
+        let inside = LatLng::new(48.8, 8.255).unwrap().to_cell(Resolution::Ten);
+        let outside = LatLng::new(10.0, 10.0).unwrap().to_cell(Resolution::Ten);
+        let sampled = conv
+            .sample_cells([inside, outside], CellValueStorage::Sorted)
+            .unwrap();
+        assert_eq!(sampled.len(), 1);
+        assert_eq!(sampled.get(inside), Some(&&25));
+    }
+
+    #[test]
+    fn test_sample_bilinear_and_area_weighted() {
+        let arr = column_gradient(50, 40);
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let inverse_transform = transform.inverse().unwrap();
+        let view = arr.view();
+        let sampler = Sampler::new(&view, &None, &transform, AxisOrder::YX).unwrap();
+
+        for lng in [8.1, 8.2234, 8.3517] {
+            let cell = LatLng::new(48.8, lng).unwrap().to_cell(Resolution::Nine);
+            let centroid: Coord = LatLng::from(cell).into();
+            let expected = inverse_transform.apply(centroid).x - 0.5;
+
+            // the gradient is linear, so bilinear interpolation is exact
+            assert_relative_eq!(
+                sampler.sample(cell, Sampling::Bilinear).unwrap(),
+                expected,
+                epsilon = 1e-6
+            );
+            // the cell is not symmetric, so the area weighted mean only roughly matches
+            let area_weighted = sampler.sample(cell, Sampling::AreaWeighted).unwrap();
+            assert!((area_weighted - expected).abs() < 0.5);
+
+            assert_eq!(
+                sampler.sample(cell, Sampling::Nearest).unwrap(),
+                (expected + 0.5).floor()
+            );
+        }
+    }
+
+    #[test]
+    fn test_sample_area_weighted_high_latitude() {
+        // the value of each pixel is its row index
+        let arr = ndarray::Array2::from_shape_fn((80, 400), |(r, _)| r as u16);
+        let transform = crate::transform::from_gdal(&[0.0, 0.05, 0.0, 82.0, 0.0, -0.05]);
+        let view = arr.view();
+        let sampler = Sampler::new(&view, &None, &transform, AxisOrder::YX).unwrap();
+        let cell = LatLng::new(80.0, 10.0).unwrap().to_cell(Resolution::Three);
+
+        // reference: the rows of points on a fine grid within the cell, weighted by the area
+        // they represent on the sphere
+        let cell_polygon = geo_types::Polygon::new(
+            cell.boundary()
+                .iter()
+                .map(|ll| coord! {x: ll.lng(), y: ll.lat()})
+                .collect(),
+            vec![],
+        );
+        let bounds = cell_polygon.bounding_rect().unwrap();
+        let (mut sum, mut sum_weights) = (0.0, 0.0);
+        for lat_i in 0..500 {
+            let lat = bounds.min().y + (lat_i as f64 + 0.5) * bounds.height() / 500.0;
+            for lng_i in 0..500 {
+                let lng = bounds.min().x + (lng_i as f64 + 0.5) * bounds.width() / 500.0;
+                let point = coord! {x: lng, y: lat};
+                if cell_polygon.contains(&point) {
+                    let weight = lat.to_radians().cos();
+                    sum += ((82.0 - lat) / 0.05).floor() * weight;
+                    sum_weights += weight;
+                }
+            }
+        }
+        assert!(sum_weights > 0.0);
+        assert_relative_eq!(
+            sampler.sample(cell, Sampling::AreaWeighted).unwrap(),
+            sum / sum_weights,
+            epsilon = 0.03
+        );
+    }
+
+    #[test]
+    fn test_sample_bicubic() {
+        // quadratic along the columns, reproduced exactly by the cubic convolution
+        let arr = ndarray::Array2::from_shape_fn((40, 50), |(_, c)| {
+            let x = c as f32 + 0.5;
+            OrderedFloat(x * x)
+        });
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let inverse_transform = transform.inverse().unwrap();
+        let view = arr.view();
+        let sampler = Sampler::new(&view, &None, &transform, AxisOrder::YX).unwrap();
+
+        for lng in [8.1, 8.2234, 8.3517] {
+            let cell = LatLng::new(48.8, lng).unwrap().to_cell(Resolution::Nine);
+            let x = inverse_transform.apply(LatLng::from(cell).into()).x;
+            let bicubic = sampler.sample(cell, Sampling::Bicubic).unwrap();
+            let bilinear = sampler.sample(cell, Sampling::Bilinear).unwrap();
+            assert_relative_eq!(bicubic, x * x, epsilon = 1e-3);
+            assert!((bicubic - x * x).abs() <= (bilinear - x * x).abs());
+        }
+
+        // at the edge of the raster the 4x4 neighbourhood is incomplete
+        let cell = LatLng::new(48.8, 8.0001)
+            .unwrap()
+            .to_cell(Resolution::Twelve);
+        assert_eq!(
+            sampler.sample(cell, Sampling::Bicubic),
+            sampler.sample(cell, Sampling::Bilinear)
+        );
+    }
+
+    #[test]
+    fn test_to_cell_value_map_numeric() {
+        let mut arr = ndarray::Array2::from_shape_fn((40, 50), |(_, c)| OrderedFloat(c as f32));
+        arr.slice_mut(ndarray::s![.., 45..])
+            .fill(OrderedFloat(f32::NAN));
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let inverse_transform = transform.inverse().unwrap();
+        let view = arr.view();
+        let nodata = Some(OrderedFloat(f32::NAN));
+        let conv = H3Converter::new(&view, &nodata, &transform, AxisOrder::YX);
+
+        let nearest = conv
+            .to_cell_value_map_numeric(
+                Resolution::Nine,
+                Sampling::Nearest,
+                CellValueStorage::Sorted,
+            )
+            .unwrap();
+        assert!(!nearest.is_empty());
+        for sampling in [Sampling::Bilinear, Sampling::Bicubic] {
+            let interpolated = conv
+                .to_cell_value_map_numeric(Resolution::Nine, sampling, CellValueStorage::Sorted)
+                .unwrap();
+            // same cells, only the values differ
+            assert_eq!(interpolated.len(), nearest.len());
+            for (cell, value) in interpolated.iter() {
+                assert!(nearest.contains(cell));
+                assert!(value.is_finite());
+                let x = inverse_transform.apply(LatLng::from(cell).into()).x;
+                if (1.5..43.5).contains(&x) {
+                    // the gradient is linear, so the interpolation is exact
+                    assert_relative_eq!(*value, x - 0.5, epsilon = 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_sample_bilinear_skips_nodata() {
+        let mut arr = ndarray::Array2::<u16>::from_elem((10, 10), 5);
+        arr.slice_mut(ndarray::s![.., 5..]).fill(0);
+        let transform = crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]);
+        let view = arr.view();
+        let sampler = Sampler::new(&view, &Some(0), &transform, AxisOrder::YX).unwrap();
+
+        // centroid between the last column with data and the first nodata column
+        let cell = LatLng::new(48.955, 8.05)
+            .unwrap()
+            .to_cell(Resolution::Twelve);
+        assert_relative_eq!(sampler.sample(cell, Sampling::Bilinear).unwrap(), 5.0);
+        assert_relative_eq!(sampler.sample(cell, Sampling::Bicubic).unwrap(), 5.0);
+        assert_relative_eq!(sampler.sample(cell, Sampling::AreaWeighted).unwrap(), 5.0);
+    }
+
+    #[test]
+    fn test_sample_antimeridian() {
+        // origin lon 179.5; pixel 0.1 deg -> spans lon 179.5..182.5
+        let arr = column_gradient(30, 10);
+        let transform = crate::transform::from_gdal(&[179.5, 0.1, 0.0, 5.0, 0.0, -0.1]);
+        let view = arr.view();
+        let conv = H3Converter::new(&view, &None, &transform, AxisOrder::YX);
+
+        // lon -178.95 equals 181.05, which is column 15
+        let cell = LatLng::new(4.5, -178.95).unwrap().to_cell(Resolution::Nine);
+        let sampled = conv
+            .sample_cells_numeric([cell], Sampling::Nearest, CellValueStorage::Hash)
+            .unwrap();
+        assert_eq!(sampled.get(cell), Some(&15.0));
+    }
+}
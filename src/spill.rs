@@ -0,0 +1,310 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::hash::Hash;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ahash::HashMap;
+use h3o::CellIndex;
+
+use crate::{CellCoverage, Error};
+
+/// Counter to create unique names for the spill directories of this process.
+static SPILL_DIRECTORY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A value -> [`CellCoverage`] map merging the results of the chunks of a conversion while
+/// staying within a memory budget.
+///
+/// When the contained cells exceed the budget, the cells of each value are written to
+/// disk as a sorted run. [`SpillingCoverageMap::finish`] merges the runs with the cells
+/// remaining in memory using an external merge.
+pub(crate) struct SpillingCoverageMap<K> {
+    memory_budget: usize,
+    spill_directory: SpillDirectory,
+    coverages: HashMap<K, CellCoverage>,
+    runs: HashMap<K, Vec<PathBuf>>,
+    n_cells: usize,
+}
+
+impl<K> SpillingCoverageMap<K>
+where
+    K: Hash + Eq + Copy,
+{
+    /// Create the map. Spilled runs are written to a newly created subdirectory of
+    /// `spill_base_directory`, which gets removed when the map is dropped.
+    pub(crate) fn new(memory_budget: usize, spill_base_directory: &Path) -> Self {
+        Self {
+            memory_budget,
+            spill_directory: SpillDirectory::new(spill_base_directory),
+            coverages: HashMap::default(),
+            runs: HashMap::default(),
+            n_cells: 0,
+        }
+    }
+
+    /// Number of runs spilled to disk.
+    #[cfg(test)]
+    fn n_runs(&self) -> usize {
+        self.runs.values().map(Vec::len).sum()
+    }
+
+    /// Merge the result of a chunk into the map, spilling to disk when the budget
+    /// gets exceeded.
+    ///
+    /// With `compact` the coverages are compacted before deciding whether to spill.
+    pub(crate) fn append(
+        &mut self,
+        chunk_map: HashMap<K, CellCoverage>,
+        compact: bool,
+    ) -> Result<(), Error> {
+        for (value, mut coverage) in chunk_map {
+            self.n_cells += coverage.len();
+            self.coverages
+                .entry(value)
+                .or_default()
+                .append(&mut coverage);
+        }
+        if self.memory_used() <= self.memory_budget {
+            return Ok(());
+        }
+
+        if compact {
+            for coverage in self.coverages.values_mut() {
+                coverage.compact()?;
+            }
+            self.n_cells = self.coverages.values().map(CellCoverage::len).sum();
+            if self.memory_used() <= self.memory_budget {
+                return Ok(());
+            }
+        }
+        self.spill()
+    }
+
+    fn memory_used(&self) -> usize {
+        self.n_cells * size_of::<CellIndex>()
+    }
+
+    /// Write the cells of all values to disk as sorted runs.
+    fn spill(&mut self) -> Result<(), Error> {
+        for (value, coverage) in self.coverages.drain() {
+            let mut cells = coverage.into_compacted_iter().collect::<Vec<_>>();
+            if cells.is_empty() {
+                continue;
+            }
+            cells.sort_unstable();
+            cells.dedup();
+
+            let path = self.spill_directory.next_run_path()?;
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for cell in cells {
+                writer.write_all(&u64::from(cell).to_le_bytes())?;
+            }
+            writer.flush()?;
+            self.runs.entry(value).or_default().push(path);
+        }
+        self.n_cells = 0;
+        Ok(())
+    }
+
+    /// Merge the spilled runs with the cells remaining in memory and finalize the
+    /// coverages.
+    ///
+    /// The values are processed one after another. When compacting, the merged cells get
+    /// compacted while they are streamed in, so only the compacted coverage needs to fit
+    /// in memory. Without compacting, the complete coverages are held in memory.
+    pub(crate) fn finish(mut self, compact: bool) -> Result<HashMap<K, CellCoverage>, Error> {
+        let mut finished = HashMap::default();
+        let cells_per_batch = (self.memory_budget / size_of::<CellIndex>()).max(1);
+
+        let mut values = self.coverages.keys().copied().collect::<Vec<_>>();
+        values.extend(
+            self.runs
+                .keys()
+                .filter(|v| !self.coverages.contains_key(*v))
+                .copied(),
+        );
+        for value in values {
+            let mut in_memory = self
+                .coverages
+                .remove(&value)
+                .map(|coverage| coverage.into_compacted_iter().collect::<Vec<_>>())
+                .unwrap_or_default();
+            in_memory.sort_unstable();
+
+            let mut sources: Vec<Box<dyn Iterator<Item = Result<CellIndex, Error>>>> =
+                vec![Box::new(in_memory.into_iter().map(Ok))];
+            for path in self.runs.remove(&value).unwrap_or_default() {
+                sources.push(Box::new(RunReader::open(&path)?));
+            }
+
+            let mut coverage = CellCoverage::default();
+            let mut next_compaction = cells_per_batch;
+            let mut n_inserted = 0;
+            let mut previous = None;
+            for cell in merge_sorted(sources) {
+                let cell = cell?;
+                if previous == Some(cell) {
+                    continue;
+                }
+                previous = Some(cell);
+                coverage.insert(cell);
+                n_inserted += 1;
+
+                if compact && n_inserted >= next_compaction {
+                    coverage.compact()?;
+                    n_inserted = coverage.len();
+                    next_compaction = (n_inserted * 2).max(cells_per_batch);
+                }
+            }
+            coverage.finalize(compact)?;
+            finished.insert(value, coverage);
+        }
+        Ok(finished)
+    }
+}
+
+/// Merge sorted streams of cells into a single sorted stream.
+fn merge_sorted<'a>(
+    mut sources: Vec<Box<dyn Iterator<Item = Result<CellIndex, Error>> + 'a>>,
+) -> impl Iterator<Item = Result<CellIndex, Error>> + 'a {
+    let mut heap = BinaryHeap::new();
+    let mut pending_error = None;
+    for (source_i, source) in sources.iter_mut().enumerate() {
+        match source.next() {
+            Some(Ok(cell)) => heap.push(Reverse((cell, source_i))),
+            Some(Err(e)) => pending_error = Some(e),
+            None => {}
+        }
+    }
+
+    std::iter::from_fn(move || {
+        if let Some(e) = pending_error.take() {
+            heap.clear();
+            return Some(Err(e));
+        }
+        let Reverse((cell, source_i)) = heap.pop()?;
+        match sources[source_i].next() {
+            Some(Ok(next_cell)) => heap.push(Reverse((next_cell, source_i))),
+            Some(Err(e)) => pending_error = Some(e),
+            None => {}
+        }
+        Some(Ok(cell))
+    })
+}
+
+/// Reads the cells of a spilled run.
+struct RunReader {
+    reader: BufReader<File>,
+}
+
+impl RunReader {
+    fn open(path: &Path) -> Result<Self, Error> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+        })
+    }
+}
+
+impl Iterator for RunReader {
+    type Item = Result<CellIndex, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = [0u8; 8];
+        match self.reader.read_exact(&mut buf) {
+            Ok(()) => Some(
+                CellIndex::try_from(u64::from_le_bytes(buf))
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e).into()),
+            ),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => None,
+            Err(e) => Some(Err(e.into())),
+        }
+    }
+}
+
+/// A directory for the spilled runs, created on first use and removed when dropped.
+struct SpillDirectory {
+    path: PathBuf,
+    created: bool,
+    n_runs: usize,
+}
+
+impl SpillDirectory {
+    fn new(base_directory: &Path) -> Self {
+        Self {
+            path: base_directory.join(format!(
+                "rasterh3-spill-{}-{}",
+                std::process::id(),
+                SPILL_DIRECTORY_COUNTER.fetch_add(1, Ordering::Relaxed)
+            )),
+            created: false,
+            n_runs: 0,
+        }
+    }
+
+    fn next_run_path(&mut self) -> Result<PathBuf, Error> {
+        if !self.created {
+            std::fs::create_dir_all(&self.path)?;
+            self.created = true;
+        }
+        self.n_runs += 1;
+        Ok(self.path.join(format!("{}.run", self.n_runs)))
+    }
+}
+
+impl Drop for SpillDirectory {
+    fn drop(&mut self) {
+        if self.created {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use h3o::{LatLng, Resolution};
+
+    use crate::CellCoverage;
+    use crate::spill::SpillingCoverageMap;
+
+    fn chunk(value: u8, parent_lat: f64) -> HashMap<u8, CellCoverage> {
+        let parent = LatLng::new(parent_lat, 9.0)
+            .unwrap()
+            .to_cell(Resolution::Six);
+        let mut coverage = CellCoverage::default();
+        for cell in parent.children(Resolution::Nine) {
+            coverage.insert(cell);
+        }
+        HashMap::from_iter([(value, coverage)])
+    }
+
+    #[test]
+    fn spills_and_merges_runs() {
+        let base_directory = std::env::temp_dir();
+        for compact in [false, true] {
+            let mut spilling = SpillingCoverageMap::new(8, &base_directory);
+            let mut expected = HashMap::<u8, CellCoverage>::default();
+            for (value, parent_lat) in [(1, 48.0), (2, 48.1), (1, 48.2), (1, 48.0), (2, 48.3)] {
+                for (value, mut coverage) in chunk(value, parent_lat) {
+                    expected.entry(value).or_default().append(&mut coverage);
+                }
+                spilling.append(chunk(value, parent_lat), compact).unwrap();
+            }
+            assert!(spilling.n_runs() >= 2);
+            let spill_path = spilling.spill_directory.path.clone();
+
+            let finished = spilling.finish(compact).unwrap();
+            assert!(!spill_path.exists());
+            assert_eq!(finished.len(), 2);
+            for (value, coverage) in expected.iter_mut() {
+                coverage.finalize(compact).unwrap();
+                assert_eq!(
+                    finished[value].compacted_iter().collect::<Vec<_>>(),
+                    coverage.compacted_iter().collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Conversion from async code, available with the `async` feature.
+//!
+//! The conversion runs on the blocking thread pool of tokio, so it does not block the
+//! executor. It needs to be started from within a tokio runtime.
+
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ahash::HashMap;
+use h3o::Resolution;
+use tokio::sync::mpsc;
+use tokio::task::JoinError;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::owned::clone_keys;
+use crate::{ArrayValue, CellCoverage, Error, OwnedH3Converter};
+
+/// Number of tile results buffered in the stream before the conversion waits for them
+/// to be consumed.
+const STREAM_BUFFER_SIZE: usize = 4;
+
+/// A stream of the results of the individual tiles of a conversion.
+pub type TileStream<T> = ReceiverStream<Result<HashMap<T, CellCoverage>, Error>>;
+
+impl<T> OwnedH3Converter<T>
+where
+    T: ArrayValue + Clone + Send + Sync + 'static,
+{
+    /// Convert to a hashmap mapping the raster values to their `CellCoverage` on the
+    /// blocking thread pool.
+    ///
+    /// See [`H3Converter::to_h3`](crate::H3Converter::to_h3).
+    pub async fn to_h3_async(
+        &self,
+        h3_resolution: Resolution,
+        compact: bool,
+    ) -> Result<HashMap<T, CellCoverage>, Error> {
+        let converter = self.clone();
+        join_result(
+            tokio::task::spawn_blocking(move || converter.to_h3(h3_resolution, compact)).await,
+        )
+    }
+
+    /// Convert on the blocking thread pool, yielding the result of each tile of the raster as
+    /// soon as it is available.
+    ///
+    /// Each cell is contained in the result of a single tile only. The coverages of a tile are
+    /// finalized, but cells of neighbouring tiles are not compacted into common parents; merge
+    /// the results using [`CellCoverage::append`] and [`CellCoverage::finalize`] for that.
+    /// A failure ends the stream after yielding the error.
+    ///
+    /// Dropping the stream cancels the conversion. Tiles already being converted are
+    /// completed, but no further tiles get started.
+    pub fn to_h3_stream(&self, h3_resolution: Resolution, compact: bool) -> TileStream<T> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let converter = self.clone();
+        tokio::task::spawn_blocking(move || send_tiles(&converter, h3_resolution, compact, &tx));
+        ReceiverStream::new(rx)
+    }
+}
+
+/// Unwrap the result of a blocking task.
+///
+/// Panics of the task are propagated, a cancellation of the task, for example during the
+/// shutdown of the runtime, is reported as [`Error::Cancelled`].
+fn join_result<R>(result: Result<Result<R, Error>, JoinError>) -> Result<R, Error> {
+    match result {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(_) => Err(Error::Cancelled),
+    }
+}
+
+/// Convert the tiles and send their results to `tx` until the receiver is dropped.
+///
+/// Returns the number of results sent.
+fn send_tiles<T>(
+    converter: &OwnedH3Converter<T>,
+    h3_resolution: Resolution,
+    compact: bool,
+    tx: &mpsc::Sender<Result<HashMap<T, CellCoverage>, Error>>,
+) -> usize
+where
+    T: ArrayValue + Clone + Send,
+{
+    let n_sent = AtomicUsize::new(0);
+    let send = |result: Result<HashMap<T, CellCoverage>, Error>| {
+        let failed = result.is_err();
+        if tx.blocking_send(result).is_err() {
+            // the receiver has been dropped
+            return ControlFlow::Break(());
+        }
+        n_sent.fetch_add(1, Ordering::Relaxed);
+        if failed {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    };
+    converter.with_converter(|conv| {
+        let installed = conv.threads().install(|| {
+            conv.for_each_rect(h3_resolution, compact, |result| {
+                send(result.map(clone_keys))
+            });
+            Ok(())
+        });
+        if let Err(e) = installed {
+            let _ = send(Err(e));
+        }
+    });
+    n_sent.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use h3o::Resolution;
+    use ndarray::Array2;
+    use tokio::sync::mpsc;
+    use tokio_stream::StreamExt;
+
+    use crate::stream::{join_result, send_tiles};
+    use crate::{AxisOrder, CellCoverage, Error, OwnedH3Converter};
+
+    fn converter() -> OwnedH3Converter<u8> {
+        let arr = Array2::from_shape_fn((200, 300), |(r, c)| ((r / 50) * 6 + c / 50) as u8);
+        OwnedH3Converter::new(
+            arr,
+            Some(0),
+            crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]),
+            AxisOrder::YX,
+        )
+    }
+
+    #[tokio::test]
+    async fn stream_matches_to_h3() {
+        let converter = converter();
+        let expected = converter
+            .to_h3_async(Resolution::Seven, false)
+            .await
+            .unwrap();
+
+        let mut stream = converter.to_h3_stream(Resolution::Seven, false);
+        let mut merged = ahash::HashMap::<u8, CellCoverage>::default();
+        let mut n_tiles = 0;
+        while let Some(tile) = stream.next().await {
+            for (value, mut coverage) in tile.unwrap() {
+                let merged_coverage = merged.entry(value).or_default();
+                // no cell is yielded by multiple tiles
+                assert!(
+                    coverage
+                        .compacted_iter()
+                        .all(|cell| !merged_coverage.covers(cell))
+                );
+                merged_coverage.append(&mut coverage);
+            }
+            n_tiles += 1;
+        }
+        assert!(n_tiles > 1);
+
+        assert_eq!(merged.len(), expected.len());
+        for (value, coverage) in merged.iter_mut() {
+            coverage.finalize(false).unwrap();
+            assert_eq!(
+                coverage.compacted_iter().collect::<Vec<_>>(),
+                expected[value].compacted_iter().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_yields_errors() {
+        let converter = converter().with_longitude_wrap(true);
+        let results = converter
+            .to_h3_stream(Resolution::Seven, false)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(matches!(results.as_slice(), [Err(Error::NotGlobal)]));
+    }
+
+    #[tokio::test]
+    async fn cancelled_tasks_yield_errors() {
+        let task = tokio::spawn(std::future::pending::<Result<(), Error>>());
+        task.abort();
+        let result = task.await;
+        assert!(result.as_ref().is_err_and(|e| e.is_cancelled()));
+        assert!(matches!(join_result(result), Err(Error::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_cancels_the_conversion() {
+        // isolated pixels, each converted as a tile of its own
+        let arr = Array2::from_shape_fn((200, 300), |(r, c)| u8::from(r % 20 == 0 && c % 20 == 0));
+        let converter = OwnedH3Converter::new(
+            arr,
+            Some(0),
+            crate::transform::from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.01]),
+            AxisOrder::YX,
+        );
+        let (tx, mut rx) = mpsc::channel(1);
+        let task = tokio::task::spawn_blocking({
+            let converter = converter.clone();
+            move || send_tiles(&converter, Resolution::Nine, false, &tx)
+        });
+
+        assert!(rx.recv().await.unwrap().is_ok());
+        drop(rx);
+
+        let n_sent = task.await.unwrap();
+        let n_tiles = converter
+            .to_h3_stream(Resolution::Nine, false)
+            .collect::<Vec<_>>()
+            .await
+            .len();
+        assert_eq!(n_tiles, 150);
+        assert!(n_sent < n_tiles / 2, "sent {n_sent} of {n_tiles} tiles");
+    }
+}
@@ -0,0 +1,91 @@
+use geo_types::{Rect, coord};
+use h3o::{CellIndex, LatLng};
+
+use crate::CellCoverage;
+
+/// Statistics of a [`CellCoverage`] or of all coverages of a map, see
+/// [`CellCoverage::summary`] and [`CellCoverageMap::summary`](crate::CellCoverageMap::summary).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoverageSummary {
+    /// Number of contained cells.
+    pub n_cells: usize,
+
+    /// Number of contained cells by their resolution. The index of the array is the resolution.
+    pub n_cells_by_resolution: [usize; 16],
+
+    /// Area covered by the cells in m².
+    pub area_m2: f64,
+
+    /// Bounding box of the boundaries of the cells in degrees, with `x` being the longitude and
+    /// `y` being the latitude. `None` when there are no cells.
+    pub bounds: Option<Rect<f64>>,
+}
+
+impl CoverageSummary {
+    /// Add the statistics of `other`.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        self.n_cells += other.n_cells;
+        for (n, other_n) in self
+            .n_cells_by_resolution
+            .iter_mut()
+            .zip(other.n_cells_by_resolution.iter())
+        {
+            *n += other_n;
+        }
+        self.area_m2 += other.area_m2;
+        self.bounds = merge_bounds(self.bounds, other.bounds);
+    }
+}
+
+impl From<&CellCoverage> for CoverageSummary {
+    fn from(coverage: &CellCoverage) -> Self {
+        Self {
+            n_cells: coverage.len(),
+            n_cells_by_resolution: coverage.len_by_resolution(),
+            area_m2: coverage.area_m2(),
+            bounds: coverage.bounds(),
+        }
+    }
+}
+
+/// Bounding box of the boundary of `cell`.
+///
+/// The box of a cell containing a pole reaches up to the pole and spans all longitudes.
+pub(crate) fn cell_bounds(cell: CellIndex) -> Option<Rect<f64>> {
+    let bounds = cell.boundary().iter().fold(None, |bounds, vertex| {
+        let point = Rect::new(
+            coord! {x: vertex.lng(), y: vertex.lat()},
+            coord! {x: vertex.lng(), y: vertex.lat()},
+        );
+        merge_bounds(bounds, Some(point))
+    })?;
+
+    let contains_pole = |lat: f64| {
+        LatLng::new(lat, 0.0)
+            .map(|pole| pole.to_cell(cell.resolution()) == cell)
+            .unwrap_or(false)
+    };
+    // cells containing a pole are located completely within its hemisphere
+    let (min_lat, max_lat) = if bounds.max().y < 0.0 && contains_pole(-90.0) {
+        (-90.0, bounds.max().y)
+    } else if bounds.min().y > 0.0 && contains_pole(90.0) {
+        (bounds.min().y, 90.0)
+    } else {
+        return Some(bounds);
+    };
+    Some(Rect::new(
+        coord! {x: -180.0, y: min_lat},
+        coord! {x: 180.0, y: max_lat},
+    ))
+}
+
+pub(crate) fn merge_bounds(a: Option<Rect<f64>>, b: Option<Rect<f64>>) -> Option<Rect<f64>> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(Rect::new(
+            coord! {x: a.min().x.min(b.min().x), y: a.min().y.min(b.min().y)},
+            coord! {x: a.max().x.max(b.max().x), y: a.max().y.max(b.max().y)},
+        )),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(feature = "rayon")]
+use std::sync::Arc;
+
+use crate::error::Error;
+
+/// The threads the parallel stages of a conversion run on.
+///
+/// Without the `rayon` feature the conversion is always single-threaded and the setting
+/// is ignored.
+#[derive(Clone, Default)]
+pub(crate) enum Threads {
+    /// The global rayon thread pool.
+    #[default]
+    Global,
+
+    /// A thread pool with the given number of threads, created for each conversion.
+    #[cfg_attr(not(feature = "rayon"), allow(dead_code))]
+    Count(usize),
+
+    /// A thread pool provided by the user.
+    #[cfg(feature = "rayon")]
+    Pool(Arc<rayon::ThreadPool>),
+}
+
+impl Threads {
+    /// Run `op` within the thread pool, so all parallel iterators used by it run on
+    /// the threads of the pool.
+    #[cfg(feature = "rayon")]
+    pub(crate) fn install<R, F>(&self, op: F) -> Result<R, Error>
+    where
+        F: FnOnce() -> Result<R, Error> + Send,
+        R: Send,
+    {
+        match self {
+            Self::Global => op(),
+            Self::Count(num_threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(*num_threads)
+                .build()?
+                .install(op),
+            Self::Pool(pool) => pool.install(op),
+        }
+    }
+
+    /// Run `op`.
+    #[cfg(not(feature = "rayon"))]
+    pub(crate) fn install<R, F>(&self, op: F) -> Result<R, Error>
+    where
+        F: FnOnce() -> Result<R, Error>,
+    {
+        op()
+    }
+}
+
+#[cfg(all(test, feature = "rayon"))]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::threads::Threads;
+
+    #[test]
+    fn install_runs_within_the_pool() {
+        let num_threads = |threads: Threads| {
+            threads
+                .install(|| Ok(rayon::current_num_threads()))
+                .unwrap()
+        };
+        assert_eq!(num_threads(Threads::Count(3)), 3);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        assert_eq!(num_threads(Threads::Pool(Arc::new(pool))), 2);
+    }
+}
@@ -14,6 +14,31 @@ pub fn from_rasterio(params: &[f64; 6]) -> AffineTransform<f64> {
     )
 }
 
+/// How the transform of a raster refers to its pixels. This corresponds to the
+/// `AREA_OR_POINT` metadata item of GDAL.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PixelRegistration {
+    /// The transform refers to the upper left corners of the pixels (`AREA_OR_POINT=Area`).
+    #[default]
+    Area,
+
+    /// The transform refers to the centers of the pixels (`AREA_OR_POINT=Point`). Used by
+    /// many elevation models.
+    Point,
+}
+
+impl PixelRegistration {
+    /// The transform referring to the upper left corners of the pixels, as used for the
+    /// conversion.
+    pub fn to_area_transform(&self, transform: &AffineTransform<f64>) -> AffineTransform<f64> {
+        match self {
+            Self::Area => *transform,
+            // move the pixel centers to the upper left corners
+            Self::Point => AffineTransform::translate(-0.5, -0.5).compose(transform),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     /*
@@ -58,7 +83,7 @@ mod tests {
     use geo::{AffineOps, AffineTransform};
     use geo_types::point;
 
-    use crate::transform::{from_gdal, from_rasterio};
+    use crate::transform::{PixelRegistration, from_gdal, from_rasterio};
 
     fn r_tiff_test_helper(gt: &AffineTransform<f64>) {
         // upper left pixel
@@ -116,4 +141,20 @@ mod tests {
         ]);
         r_tiff_test_helper(&gt);
     }
+
+    #[test]
+    fn test_point_registration() {
+        let gt = from_gdal(&[8.0, 0.01, 0.0, 49.0, 0.0, -0.02]);
+        assert_eq!(PixelRegistration::Area.to_area_transform(&gt), gt);
+
+        let area_gt = PixelRegistration::Point.to_area_transform(&gt);
+        // the upper left corner is half a pixel away from the center of the first pixel
+        let corner_ul = point! { x: 0., y: 0. }.affine_transform(&area_gt);
+        assert_relative_eq!(corner_ul.x(), 7.995);
+        assert_relative_eq!(corner_ul.y(), 49.01);
+
+        let center_ul = point! { x: 0.5, y: 0.5 }.affine_transform(&area_gt);
+        assert_relative_eq!(center_ul.x(), 8.0);
+        assert_relative_eq!(center_ul.y(), 49.0);
+    }
 }
@@ -1,4 +1,5 @@
-use geo_types::{Rect, coord};
+use geo::{Area, BooleanOps, BoundingRect, MapCoords, Translate};
+use geo_types::{Polygon, Rect, coord};
 
 /// Normalize a longitude to coordinate to ensure it's within [-180,180]
 #[inline(always)]
@@ -64,9 +65,108 @@ pub(crate) fn split_rect_at_antimeridian(rect: Rect) -> Vec<SplittedRect> {
     splits
 }
 
+#[derive(Debug)]
+pub(crate) struct SplittedPolygon {
+    pub(crate) polygon: Polygon,
+    pub(crate) difference_due_to_antimeridian_split: f64,
+}
+
+/// Split a polygon at the antimeridian.
+///
+/// Axis-aligned rectangles are split using [`split_rect_at_antimeridian`]. All other polygons,
+/// like the windows of rotated rasters, get shifted by multiples of 360 degrees and clipped to
+/// the valid longitude range.
+pub(crate) fn split_polygon_at_antimeridian(polygon: Polygon) -> Vec<SplittedPolygon> {
+    let Some(bbox) = polygon.bounding_rect() else {
+        return vec![];
+    };
+    if is_axis_aligned_rect(&polygon, &bbox) {
+        return split_rect_at_antimeridian(bbox)
+            .into_iter()
+            .map(|splitted| SplittedPolygon {
+                polygon: splitted.rect.to_polygon(),
+                difference_due_to_antimeridian_split: splitted.difference_due_to_antimeridian_split,
+            })
+            .collect();
+    }
+
+    let first_turn = ((bbox.min().x + 180.0) / 360.0).floor() as i64;
+    // a polygon ending exactly at the antimeridian belongs to the turn west of it
+    let last_turn = (((bbox.max().x + 180.0) / 360.0).ceil() as i64 - 1).max(first_turn);
+    if first_turn == last_turn {
+        // not crossing the antimeridian
+        let difference = first_turn as f64 * 360.0;
+        return vec![SplittedPolygon {
+            polygon: polygon.translate(-difference, 0.0),
+            difference_due_to_antimeridian_split: difference,
+        }];
+    }
+
+    let valid = Rect::new(
+        coord! {x: -180.0, y: bbox.min().y},
+        coord! {x: 180.0, y: bbox.max().y},
+    )
+    .to_polygon();
+
+    let mut splits = Vec::new();
+    for turn in first_turn..=last_turn {
+        let difference = turn as f64 * 360.0;
+        for clipped in polygon.translate(-difference, 0.0).intersection(&valid) {
+            // skip degenerate polygons of shapes only touching the antimeridian
+            if clipped.unsigned_area() > 0.0 {
+                splits.push(SplittedPolygon {
+                    // remove numerical noise of the clipping
+                    polygon: clipped.map_coords(|c| coord! {x: c.x.clamp(-180.0, 180.0), y: c.y}),
+                    difference_due_to_antimeridian_split: difference,
+                });
+            }
+        }
+    }
+    splits
+}
+
+/// Clip a polygon to the valid latitude range of [-90, 90].
+///
+/// Returns `None` when the polygon is located completely beyond the poles.
+pub(crate) fn clip_to_valid_latitudes(polygon: Polygon) -> Option<Polygon> {
+    let bbox = polygon.bounding_rect()?;
+    if bbox.min().y >= -90.0 && bbox.max().y <= 90.0 {
+        return Some(polygon);
+    }
+    if bbox.min().y >= 90.0 || bbox.max().y <= -90.0 {
+        return None;
+    }
+    if is_axis_aligned_rect(&polygon, &bbox) {
+        return Some(polygon.map_coords(|c| coord! {x: c.x, y: c.y.clamp(-90.0, 90.0)}));
+    }
+    let valid = Rect::new(
+        coord! {x: bbox.min().x, y: -90.0},
+        coord! {x: bbox.max().x, y: 90.0},
+    )
+    .to_polygon();
+    // a convex window clipped by a band of latitudes stays a single polygon
+    polygon
+        .intersection(&valid)
+        .into_iter()
+        .find(|clipped| clipped.unsigned_area() > 0.0)
+        // remove numerical noise of the clipping
+        .map(|clipped| clipped.map_coords(|c| coord! {x: c.x, y: c.y.clamp(-90.0, 90.0)}))
+}
+
+fn is_axis_aligned_rect(polygon: &Polygon, bbox: &Rect) -> bool {
+    polygon.interiors().is_empty()
+        && polygon.exterior().coords().all(|c| {
+            (c.x == bbox.min().x || c.x == bbox.max().x)
+                && (c.y == bbox.min().y || c.y == bbox.max().y)
+        })
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::util::split_rect_at_antimeridian;
+    use crate::util::{
+        clip_to_valid_latitudes, split_polygon_at_antimeridian, split_rect_at_antimeridian,
+    };
+    use geo::{Area, BooleanOps, Rotate, Translate};
     use geo_types::{Rect, coord};
 
     #[test]
@@ -203,4 +303,72 @@ mod tests {
             coord! {x: -178.0, y: 23.0},
         ));
     }
+
+    #[test]
+    fn test_split_polygon_at_antimeridian_rect() {
+        // axis-aligned rects are split exactly like using `split_rect_at_antimeridian`
+        let rect = Rect::new(coord! {x: 178.0, y: 12.0}, coord! {x: 185.0, y: 23.0});
+        let splitted = split_polygon_at_antimeridian(rect.to_polygon());
+        let splitted_rects = split_rect_at_antimeridian(rect);
+        assert_eq!(splitted.len(), splitted_rects.len());
+        for (s, r) in splitted.iter().zip(splitted_rects.iter()) {
+            assert_eq!(s.polygon, r.rect.to_polygon());
+            assert_eq!(
+                s.difference_due_to_antimeridian_split,
+                r.difference_due_to_antimeridian_split
+            );
+        }
+    }
+
+    #[test]
+    fn test_split_polygon_at_antimeridian_rotated() {
+        let polygon = Rect::new(coord! {x: 176.0, y: 12.0}, coord! {x: 186.0, y: 23.0})
+            .to_polygon()
+            .rotate_around_center(30.0);
+        let splitted = split_polygon_at_antimeridian(polygon.clone());
+        assert_eq!(splitted.len(), 2);
+
+        let mut area = 0.0;
+        for s in splitted.iter() {
+            for c in s.polygon.exterior().coords() {
+                assert!((-180.0..=180.0).contains(&c.x));
+            }
+            // shifting back results in the parts of the original polygon
+            let restored = s
+                .polygon
+                .translate(s.difference_due_to_antimeridian_split, 0.0);
+            let part_area = restored.unsigned_area();
+            assert!(part_area > 0.0);
+            approx::assert_relative_eq!(
+                restored.intersection(&polygon).unsigned_area(),
+                part_area,
+                max_relative = 1e-6
+            );
+            area += part_area;
+        }
+        approx::assert_relative_eq!(area, polygon.unsigned_area(), max_relative = 1e-6);
+    }
+
+    #[test]
+    fn test_clip_to_valid_latitudes() {
+        let inside = Rect::new(coord! {x: 10.0, y: 80.0}, coord! {x: 20.0, y: 90.0}).to_polygon();
+        assert_eq!(clip_to_valid_latitudes(inside.clone()), Some(inside));
+
+        let beyond = Rect::new(coord! {x: 10.0, y: 90.0}, coord! {x: 20.0, y: 95.0}).to_polygon();
+        assert_eq!(clip_to_valid_latitudes(beyond), None);
+
+        let overflowing =
+            Rect::new(coord! {x: 10.0, y: -95.0}, coord! {x: 20.0, y: -85.0}).to_polygon();
+        assert_eq!(
+            clip_to_valid_latitudes(overflowing),
+            Some(Rect::new(coord! {x: 10.0, y: -90.0}, coord! {x: 20.0, y: -85.0}).to_polygon())
+        );
+
+        let rotated = Rect::new(coord! {x: 10.0, y: 80.0}, coord! {x: 20.0, y: 95.0})
+            .to_polygon()
+            .rotate_around_center(20.0);
+        let clipped = clip_to_valid_latitudes(rotated).unwrap();
+        assert!(clipped.exterior().coords().all(|c| c.y <= 90.0));
+        assert!(clipped.exterior().coords().any(|c| c.y == 90.0));
+    }
 }